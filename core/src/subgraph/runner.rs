@@ -710,6 +710,7 @@ where
                     block_ptr: Some(block_ptr),
                     handler: None,
                     deterministic,
+                    block_hash_only: None,
                 };
 
                 match deterministic {
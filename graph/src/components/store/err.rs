@@ -50,6 +50,17 @@ pub enum StoreError {
     Poisoned,
     #[error("panic in subgraph writer: {0}")]
     WriterPanic(JoinError),
+    #[error(
+        "the current reorg depth {current} of subgraph `{deployment}` \
+         exceeds its maximum reorg depth {max}"
+    )]
+    ReorgDepthExceeded {
+        deployment: String,
+        current: i32,
+        max: i32,
+    },
+    #[error("query was canceled because it exceeded its statement timeout")]
+    QueryTimeout,
 }
 
 // Convenience to report a constraint violation
@@ -5,14 +5,17 @@ use hex;
 use lazy_static::lazy_static;
 use rand::rngs::OsRng;
 use rand::Rng;
+use semver::Version;
 use stable_hash_legacy::{SequenceNumber, StableHash, StableHasher};
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use std::{fmt, fmt::Display};
 
-use super::DeploymentHash;
+use super::{DeploymentHash, SubgraphFeature};
 use crate::data::graphql::TryFromValue;
 use crate::data::store::Value;
 use crate::data::subgraph::SubgraphManifest;
+use crate::prelude::web3::types::H256;
 use crate::prelude::*;
 use crate::{blockchain::Blockchain, components::store::EntityType};
 
@@ -156,22 +159,96 @@ pub struct SubgraphDeploymentEntity {
     pub max_reorg_depth: i32,
 }
 
+/// The spec version of a subgraph manifest. Spec versions are compared
+/// according to semver rules
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpecVersion(Version);
+
+impl SpecVersion {
+    pub fn as_version(&self) -> &Version {
+        &self.0
+    }
+}
+
+impl From<Version> for SpecVersion {
+    fn from(version: Version) -> Self {
+        SpecVersion(version)
+    }
+}
+
+impl FromStr for SpecVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SpecVersion, Error> {
+        Version::parse(s)
+            .map(SpecVersion)
+            .map_err(|e| anyhow!("failed to parse `{}` as a spec version: {}", s, e))
+    }
+}
+
+impl Display for SpecVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The features that a subgraph manifest declares. Features that this
+/// version of graph-node does not know are kept so that they are not lost
+/// when the features are written back to the store
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    raw: Vec<String>,
+    known: BTreeSet<SubgraphFeature>,
+}
+
+impl Features {
+    /// Return `true` if `feature` is one of the declared features
+    pub fn has(&self, feature: SubgraphFeature) -> bool {
+        self.known.contains(&feature)
+    }
+
+    /// The declared features as strings, including the ones that we do
+    /// not know
+    pub fn as_strings(&self) -> &[String] {
+        &self.raw
+    }
+}
+
+impl From<Vec<String>> for Features {
+    fn from(raw: Vec<String>) -> Self {
+        let known = raw
+            .iter()
+            .filter_map(|feature| SubgraphFeature::from_str(feature).ok())
+            .collect();
+        Features { raw, known }
+    }
+}
+
+impl From<&BTreeSet<SubgraphFeature>> for Features {
+    fn from(features: &BTreeSet<SubgraphFeature>) -> Self {
+        Features {
+            raw: features.iter().map(|f| f.to_string()).collect(),
+            known: features.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SubgraphManifestEntity {
-    pub spec_version: String,
+    pub spec_version: SpecVersion,
     pub description: Option<String>,
     pub repository: Option<String>,
-    pub features: Vec<String>,
+    pub features: Features,
     pub schema: String,
 }
 
 impl<'a, C: Blockchain> From<&'a super::SubgraphManifest<C>> for SubgraphManifestEntity {
     fn from(manifest: &'a super::SubgraphManifest<C>) -> Self {
         Self {
-            spec_version: manifest.spec_version.to_string(),
+            spec_version: SpecVersion::from(manifest.spec_version.clone()),
             description: manifest.description.clone(),
             repository: manifest.repository.clone(),
-            features: manifest.features.iter().map(|f| f.to_string()).collect(),
+            features: Features::from(&manifest.features),
             schema: manifest.schema.document.clone().to_string(),
         }
     }
@@ -186,6 +263,12 @@ pub struct SubgraphError {
 
     // `true` if we are certain the error is deterministic. If in doubt, this is `false`.
     pub deterministic: bool,
+
+    /// The hash of the block at which the error happened as stored in the
+    /// database, even if we do not know the block number and `block_ptr`
+    /// is therefore `None`. This is only ever set for errors that were
+    /// loaded from the store
+    pub block_hash_only: Option<H256>,
 }
 
 impl Display for SubgraphError {
@@ -209,6 +292,8 @@ impl StableHash for SubgraphError {
             block_ptr,
             handler,
             deterministic,
+            // This is purely informational and must not change the hash
+            block_hash_only: _,
         } = self;
         subgraph_id.stable_hash(sequence_number.next_child(), state);
         message.stable_hash(sequence_number.next_child(), state);
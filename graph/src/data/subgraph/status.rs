@@ -1,9 +1,10 @@
 //! Support for the indexing status API
 
 use super::schema::{SubgraphError, SubgraphHealth};
-use crate::components::store::DeploymentId;
+use crate::components::store::{DeploymentId, StoreError};
 use crate::data::graphql::{object, IntoValue};
-use crate::prelude::{r, web3::types::H256, BlockPtr, Value};
+use crate::prelude::{anyhow, r, web3::types::H256, BlockPtr, Value};
+use std::fmt;
 
 pub enum Filter {
     /// Get all versions for the named subgraph
@@ -18,7 +19,7 @@ pub enum Filter {
 }
 
 /// Light wrapper around `EthereumBlockPointer` that is compatible with GraphQL values.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct EthereumBlock(BlockPtr);
 
 impl EthereumBlock {
@@ -26,6 +27,26 @@ impl EthereumBlock {
         EthereumBlock(BlockPtr::from((hash, number)))
     }
 
+    /// Create a block from a hash given as `0x` followed by 64 hex digits
+    pub fn from_hex(hash_hex: &str, number: u64) -> Result<Self, StoreError> {
+        let invalid = |reason: &str| {
+            StoreError::Unknown(anyhow!("invalid block hash `{}`: {}", hash_hex, reason))
+        };
+
+        let digits = hash_hex
+            .strip_prefix("0x")
+            .ok_or_else(|| invalid("it must start with `0x`"))?;
+        if digits.len() != 2 * H256::len_bytes() {
+            return Err(invalid(&format!(
+                "it must have {} hex digits but has {}",
+                2 * H256::len_bytes(),
+                digits.len()
+            )));
+        }
+        let bytes = hex::decode(digits).map_err(|e| invalid(&e.to_string()))?;
+        Ok(Self::new(H256::from_slice(&bytes), number))
+    }
+
     pub fn to_ptr(self) -> BlockPtr {
         self.0
     }
@@ -54,7 +75,7 @@ impl From<BlockPtr> for EthereumBlock {
 /// Indexing status information related to the chain. Right now, we only
 /// support Ethereum, but once we support more chains, we'll have to turn this into
 /// an enum
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ChainInfo {
     /// The network name (e.g. `mainnet`, `ropsten`, `rinkeby`, `kovan` or `goerli`).
     pub network: String,
@@ -86,7 +107,7 @@ impl IntoValue for ChainInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Info {
     pub id: DeploymentId,
 
@@ -96,6 +117,10 @@ pub struct Info {
     /// Whether or not the subgraph has synced all the way to the current chain head.
     pub synced: bool,
     pub health: SubgraphHealth,
+    /// Whether the subgraph is marked as failed. This is tracked separately
+    /// from `health` in the database, and the two can briefly disagree
+    /// while the subgraph transitions between states
+    pub failed: bool,
     pub fatal_error: Option<SubgraphError>,
     pub non_fatal_errors: Vec<SubgraphError>,
 
@@ -108,6 +133,101 @@ pub struct Info {
     pub node: Option<String>,
 }
 
+impl Info {
+    /// Create an `Info` for `subgraph` on `network` without any errors,
+    /// blocks or entities. This is mostly useful for tests and mocks that
+    /// need a status without going through the store
+    pub fn new_minimal(
+        subgraph: String,
+        network: String,
+        synced: bool,
+        health: SubgraphHealth,
+    ) -> Self {
+        Info {
+            id: DeploymentId::new(0),
+            subgraph,
+            synced,
+            health,
+            failed: health.is_failed(),
+            fatal_error: None,
+            non_fatal_errors: vec![],
+            chains: vec![ChainInfo {
+                network,
+                chain_head_block: None,
+                earliest_block: None,
+                latest_block: None,
+            }],
+            entity_count: 0,
+            node: None,
+        }
+    }
+
+    /// The handler that caused the fatal error, if the subgraph failed and
+    /// the error was raised in a handler
+    pub fn failing_handler(&self) -> Option<&str> {
+        self.fatal_error
+            .as_ref()
+            .and_then(|error| error.handler.as_deref())
+    }
+
+    /// The status as JSON in the same shape that the indexing status API
+    /// uses, but leaving out all fields that are `null` or empty lists
+    pub fn to_compact_json(&self) -> serde_json::Value {
+        let value = serde_json::to_value(self.clone().into_value())
+            .expect("serializing a status to JSON can not fail");
+        compact(value).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+/// Remove `null` and empty lists from `value`, or return `None` if nothing
+/// is left of it. List elements that compact to nothing are left out of
+/// the list, and a list that becomes empty that way is removed, too
+fn compact(value: serde_json::Value) -> Option<serde_json::Value> {
+    use serde_json::Value as J;
+
+    match value {
+        J::Null => None,
+        J::Array(values) => {
+            let values: Vec<_> = values.into_iter().filter_map(compact).collect();
+            if values.is_empty() {
+                None
+            } else {
+                Some(J::Array(values))
+            }
+        }
+        J::Object(map) => Some(J::Object(
+            map.into_iter()
+                .filter_map(|(key, value)| compact(value).map(|value| (key, value)))
+                .collect(),
+        )),
+        value => Some(value),
+    }
+}
+
+/// A one-line summary of the status, e.g., `deployment=Qm.. health=healthy
+/// synced=true latest=#12345 errors=0`. Only the latest block of the first
+/// chain is shown
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let latest = self
+            .chains
+            .first()
+            .and_then(|chain| chain.latest_block.as_ref())
+            .map(|block| format!("#{}", block.number()))
+            .unwrap_or_else(|| "none".to_string());
+        let errors = self.fatal_error.iter().count() + self.non_fatal_errors.len();
+        write!(
+            f,
+            "deployment={} health={} synced={} latest={} errors={}",
+            self.subgraph,
+            self.health.as_str(),
+            self.synced,
+            latest,
+            errors
+        )
+    }
+}
+
 impl IntoValue for Info {
     fn into_value(self) -> r::Value {
         let Info {
@@ -117,6 +237,7 @@ impl IntoValue for Info {
             entity_count,
             fatal_error,
             health,
+            failed: _,
             node,
             non_fatal_errors,
             synced,
@@ -129,6 +250,7 @@ impl IntoValue for Info {
                 block_ptr,
                 handler,
                 deterministic,
+                block_hash_only: _,
             } = subgraph_error;
 
             object! {
@@ -164,3 +286,260 @@ impl IntoValue for Info {
         }
     }
 }
+
+/// A block as the GraphQL API encodes it: the hash as `0x`-prefixed hex,
+/// and the number as a decimal string
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockGraphql {
+    pub hash: String,
+    pub number: String,
+}
+
+impl From<&BlockPtr> for BlockGraphql {
+    fn from(ptr: &BlockPtr) -> Self {
+        BlockGraphql {
+            hash: format!("0x{}", ptr.hash_hex()),
+            number: ptr.number.to_string(),
+        }
+    }
+}
+
+impl From<EthereumBlock> for BlockGraphql {
+    fn from(block: EthereumBlock) -> Self {
+        BlockGraphql::from(&block.0)
+    }
+}
+
+/// A `ChainInfo` with blocks encoded as `BlockGraphql`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainInfoGraphql {
+    pub network: String,
+    pub chain_head_block: Option<BlockGraphql>,
+    pub earliest_block: Option<BlockGraphql>,
+    pub latest_block: Option<BlockGraphql>,
+}
+
+impl From<ChainInfo> for ChainInfoGraphql {
+    fn from(chain: ChainInfo) -> Self {
+        ChainInfoGraphql {
+            network: chain.network,
+            chain_head_block: chain.chain_head_block.map(BlockGraphql::from),
+            earliest_block: chain.earliest_block.map(BlockGraphql::from),
+            latest_block: chain.latest_block.map(BlockGraphql::from),
+        }
+    }
+}
+
+/// A `SubgraphError` with its block encoded as `BlockGraphql`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubgraphErrorGraphql {
+    pub subgraph_id: String,
+    pub message: String,
+    pub handler: Option<String>,
+    pub block: Option<BlockGraphql>,
+    pub deterministic: bool,
+}
+
+impl From<SubgraphError> for SubgraphErrorGraphql {
+    fn from(error: SubgraphError) -> Self {
+        SubgraphErrorGraphql {
+            subgraph_id: error.subgraph_id.to_string(),
+            message: error.message,
+            handler: error.handler,
+            block: error.block_ptr.as_ref().map(BlockGraphql::from),
+            deterministic: error.deterministic,
+        }
+    }
+}
+
+/// An `Info` with all values encoded the way the GraphQL API expects them,
+/// so that resolvers do not have to encode block numbers, hashes, and
+/// counts themselves
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InfoGraphql {
+    pub id: DeploymentId,
+    pub subgraph: String,
+    pub synced: bool,
+    pub health: String,
+    pub failed: bool,
+    pub fatal_error: Option<SubgraphErrorGraphql>,
+    pub non_fatal_errors: Vec<SubgraphErrorGraphql>,
+    pub chains: Vec<ChainInfoGraphql>,
+    pub entity_count: String,
+    pub node: Option<String>,
+}
+
+impl From<Info> for InfoGraphql {
+    fn from(info: Info) -> Self {
+        InfoGraphql {
+            id: info.id,
+            subgraph: info.subgraph,
+            synced: info.synced,
+            health: info.health.as_str().to_string(),
+            failed: info.failed,
+            fatal_error: info.fatal_error.map(SubgraphErrorGraphql::from),
+            non_fatal_errors: info
+                .non_fatal_errors
+                .into_iter()
+                .map(SubgraphErrorGraphql::from)
+                .collect(),
+            chains: info
+                .chains
+                .into_iter()
+                .map(ChainInfoGraphql::from)
+                .collect(),
+            entity_count: info.entity_count.to_string(),
+            node: info.node,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::DeploymentHash;
+
+    fn info_with_error(handler: Option<&str>) -> Info {
+        let mut info = Info::new_minimal(
+            "testSubgraph".to_string(),
+            "mainnet".to_string(),
+            false,
+            SubgraphHealth::Failed,
+        );
+        info.fatal_error = Some(SubgraphError {
+            subgraph_id: DeploymentHash::new("testSubgraph").unwrap(),
+            message: "boom".to_string(),
+            block_ptr: None,
+            handler: handler.map(str::to_string),
+            deterministic: true,
+            block_hash_only: None,
+        });
+        info
+    }
+
+    #[test]
+    fn failing_handler() {
+        let info = info_with_error(Some("handleTransfer"));
+        assert_eq!(Some("handleTransfer"), info.failing_handler());
+
+        let info = info_with_error(None);
+        assert_eq!(None, info.failing_handler());
+
+        let info = Info::new_minimal(
+            "testSubgraph".to_string(),
+            "mainnet".to_string(),
+            true,
+            SubgraphHealth::Healthy,
+        );
+        assert_eq!(None, info.failing_handler());
+    }
+
+    #[test]
+    fn compact_json() {
+        let info = Info::new_minimal(
+            "testSubgraph".to_string(),
+            "mainnet".to_string(),
+            true,
+            SubgraphHealth::Healthy,
+        );
+        let json = info.to_compact_json();
+        let status = json.as_object().unwrap();
+        assert_eq!(
+            Some(&serde_json::json!("testSubgraph")),
+            status.get("subgraph")
+        );
+        assert_eq!(Some(&serde_json::json!(true)), status.get("synced"));
+        assert!(!status.contains_key("fatalError"));
+        assert!(!status.contains_key("nonFatalErrors"));
+        assert!(!status.contains_key("node"));
+
+        let chain = status["chains"][0].as_object().unwrap();
+        assert_eq!(Some(&serde_json::json!("mainnet")), chain.get("network"));
+        assert!(!chain.contains_key("chainHeadBlock"));
+        assert!(!chain.contains_key("latestBlock"));
+
+        let json = info_with_error(None).to_compact_json();
+        let error = json["fatalError"].as_object().unwrap();
+        assert_eq!(Some(&serde_json::json!("boom")), error.get("message"));
+        assert!(!error.contains_key("handler"));
+
+        // Compacting does not put nulls into lists
+        assert_eq!(
+            Some(serde_json::json!({ "kept": [1, "x"] })),
+            compact(serde_json::json!({
+                "nested": [[]],
+                "kept": [1, null, [], "x"],
+            }))
+        );
+    }
+
+    #[test]
+    fn block_from_hex() {
+        let hash = format!("0x{}", "0".repeat(63) + "a");
+        let block = EthereumBlock::from_hex(&hash, 3).unwrap();
+        assert_eq!(
+            BlockPtr::from((H256::from_low_u64_be(10), 3u64)),
+            block.to_ptr()
+        );
+
+        assert!(EthereumBlock::from_hex(&hash[2..], 3).is_err());
+        assert!(EthereumBlock::from_hex(&hash[..65], 3).is_err());
+        assert!(EthereumBlock::from_hex(&format!("0x{}", "g".repeat(64)), 3).is_err());
+    }
+
+    #[test]
+    fn graphql_encoding() {
+        let mut info = info_with_error(Some("handleTransfer"));
+        info.entity_count = 12;
+        info.chains[0].latest_block = Some(EthereumBlock::new(H256::from_low_u64_be(1), 7));
+
+        let info = InfoGraphql::from(info);
+        assert_eq!("failed", info.health);
+        assert_eq!("12", info.entity_count);
+        assert_eq!(
+            Some(BlockGraphql {
+                hash: format!("0x{}", "0".repeat(63) + "1"),
+                number: "7".to_string()
+            }),
+            info.chains[0].latest_block
+        );
+        assert_eq!(None, info.chains[0].chain_head_block);
+
+        let error = info.fatal_error.unwrap();
+        assert_eq!("boom", error.message);
+        assert_eq!(Some("handleTransfer".to_string()), error.handler);
+        assert_eq!(None, error.block);
+    }
+
+    #[test]
+    fn display_summary() {
+        let mut info = Info::new_minimal(
+            "testSubgraph".to_string(),
+            "mainnet".to_string(),
+            true,
+            SubgraphHealth::Healthy,
+        );
+        assert_eq!(
+            "deployment=testSubgraph health=healthy synced=true latest=none errors=0",
+            info.to_string()
+        );
+
+        info.chains[0].latest_block = Some(EthereumBlock::new(H256::zero(), 12345));
+        assert_eq!(
+            "deployment=testSubgraph health=healthy synced=true latest=#12345 errors=0",
+            info.to_string()
+        );
+
+        info.chains.clear();
+        assert_eq!(
+            "deployment=testSubgraph health=healthy synced=true latest=none errors=0",
+            info.to_string()
+        );
+
+        let info = info_with_error(None);
+        assert_eq!(
+            "deployment=testSubgraph health=failed synced=false latest=none errors=1",
+            info.to_string()
+        );
+    }
+}
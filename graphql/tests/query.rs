@@ -2003,6 +2003,7 @@ fn non_fatal_errors() {
             block_ptr: Some(BLOCK_TWO.block_ptr()),
             handler: Some("handleMoo".to_string()),
             deterministic: true,
+            block_hash_only: None,
         };
 
         transact_errors(&*STORE, &deployment, BLOCK_TWO.block_ptr(), vec![err])
@@ -217,6 +217,7 @@ impl<C: Blockchain> WasmInstance<C> {
                 block_ptr: Some(self.instance_ctx().ctx.block_ptr.cheap_clone()),
                 handler: Some(handler.to_string()),
                 deterministic: true,
+                block_hash_only: None,
             };
             self.instance_ctx_mut()
                 .ctx
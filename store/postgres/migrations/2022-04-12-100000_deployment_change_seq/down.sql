@@ -0,0 +1,8 @@
+drop trigger subgraph_deployment_change_seq_trigger
+    on subgraphs.subgraph_deployment;
+drop function subgraphs.subgraph_deployment_bump_change_seq();
+alter table
+    subgraphs.subgraph_deployment
+drop
+    column change_seq;
+drop sequence subgraphs.subgraph_deployment_change_seq;
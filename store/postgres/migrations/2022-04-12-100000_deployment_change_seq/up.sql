@@ -0,0 +1,26 @@
+-- Number every change to a row in subgraph_deployment so that external
+-- tools can poll for changed deployments with a watermark
+create sequence subgraphs.subgraph_deployment_change_seq;
+
+alter table
+    subgraphs.subgraph_deployment
+add
+    column change_seq bigint not null
+        default nextval('subgraphs.subgraph_deployment_change_seq');
+
+create function subgraphs.subgraph_deployment_bump_change_seq()
+returns trigger as $$
+begin
+    new.change_seq := nextval('subgraphs.subgraph_deployment_change_seq');
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger subgraph_deployment_change_seq_trigger
+    before update on subgraphs.subgraph_deployment
+    for each row execute procedure subgraphs.subgraph_deployment_bump_change_seq();
+
+-- There is deliberately no index on change_seq: the table is updated on
+-- every block, and an index on a column that every update changes would
+-- prevent HOT updates. With one row per deployment, polling for changes
+-- with a sequential scan is cheap
@@ -2,7 +2,7 @@
 //! into these methods must be for the shard that holds the actual
 //! deployment data and metadata
 use crate::{
-    detail::GraphNodeVersion,
+    detail::{GraphNodeVersion, NewErrorDetail},
     functions::{coalesce_binary, coalesce_numeric},
 };
 use diesel::{
@@ -22,11 +22,10 @@ use graph::data::subgraph::{
     SubgraphFeature,
 };
 use graph::prelude::{
-    anyhow, bigdecimal::ToPrimitive, hex, web3::types::H256, BigDecimal, BlockNumber, BlockPtr,
+    anyhow, bigdecimal::ToPrimitive, web3::types::H256, BigDecimal, BlockNumber, BlockPtr,
     DeploymentHash, DeploymentState, Schema, StoreError,
 };
-use stable_hash_legacy::crypto::SetHasher;
-use std::{collections::BTreeSet, convert::TryFrom, ops::Bound};
+use std::{collections::BTreeSet, convert::TryFrom};
 use std::{str::FromStr, sync::Arc};
 
 use crate::connection_pool::ForeignServer;
@@ -41,7 +40,7 @@ pub enum SubgraphHealth {
 }
 
 impl SubgraphHealth {
-    fn is_failed(&self) -> bool {
+    pub(crate) fn is_failed(&self) -> bool {
         use graph::data::subgraph::schema::SubgraphHealth as H;
 
         H::from(*self).is_failed()
@@ -61,6 +60,19 @@ impl From<SubgraphHealth> for graph::data::subgraph::schema::SubgraphHealth {
     }
 }
 
+impl From<graph::data::subgraph::schema::SubgraphHealth> for SubgraphHealth {
+    fn from(health: graph::data::subgraph::schema::SubgraphHealth) -> Self {
+        use graph::data::subgraph::schema::SubgraphHealth as H;
+        use SubgraphHealth as Db;
+
+        match health {
+            H::Failed => Db::Failed,
+            H::Healthy => Db::Healthy,
+            H::Unhealthy => Db::Unhealthy,
+        }
+    }
+}
+
 table! {
     subgraphs.subgraph_deployment (id) {
         id -> Integer,
@@ -85,6 +97,7 @@ table! {
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
         firehose_cursor -> Nullable<Text>,
+        change_seq -> BigInt,
     }
 }
 
@@ -126,7 +139,12 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(subgraph_deployment, subgraph_error);
+allow_tables_to_appear_in_same_query!(
+    subgraph_deployment,
+    subgraph_error,
+    subgraph_manifest,
+    graph_node_versions
+);
 
 /// Look up the graft point for the given subgraph in the database and
 /// return it. If `pending_only` is `true`, only return `Some(_)` if the
@@ -607,41 +625,13 @@ pub fn exists_and_synced(conn: &PgConnection, id: &str) -> Result<bool, StoreErr
 
 // Does nothing if the error already exists. Returns the error id.
 fn insert_subgraph_error(conn: &PgConnection, error: &SubgraphError) -> anyhow::Result<String> {
-    use subgraph_error as e;
-
-    let error_id = hex::encode(&stable_hash_legacy::utils::stable_hash::<SetHasher, _>(
-        &error,
-    ));
-    let SubgraphError {
-        subgraph_id,
-        message,
-        handler,
-        block_ptr,
-        deterministic,
-    } = error;
-
-    let block_num = match &block_ptr {
-        None => {
-            assert_eq!(*deterministic, false);
-            crate::block_range::BLOCK_UNVERSIONED
-        }
-        Some(block) => crate::block_range::block_number(block),
-    };
-
-    insert_into(e::table)
-        .values((
-            e::id.eq(&error_id),
-            e::subgraph_id.eq(subgraph_id.as_str()),
-            e::message.eq(message),
-            e::handler.eq(handler),
-            e::deterministic.eq(deterministic),
-            e::block_hash.eq(block_ptr.as_ref().map(|ptr| ptr.hash_slice())),
-            e::block_range.eq((Bound::Included(block_num), Bound::Unbounded)),
-        ))
-        .on_conflict_do_nothing()
-        .execute(conn)?;
+    if error.block_ptr.is_none() {
+        assert_eq!(error.deterministic, false);
+    }
 
-    Ok(error_id)
+    let error = NewErrorDetail::from(error);
+    error.insert(conn)?;
+    Ok(error.id().to_string())
 }
 
 pub fn fail(
@@ -947,10 +937,10 @@ pub fn create_deployment(
 
     let manifest_values = (
         m::id.eq(site.id),
-        m::spec_version.eq(spec_version),
+        m::spec_version.eq(spec_version.to_string()),
         m::description.eq(description),
         m::repository.eq(repository),
-        m::features.eq(features),
+        m::features.eq(features.as_strings()),
         m::schema.eq(schema),
         m::graph_node_version_id.eq(graph_node_version_id),
     );
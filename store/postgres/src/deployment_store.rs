@@ -206,7 +206,7 @@ impl DeploymentStore {
         site: &Site,
     ) -> Result<SubgraphDeploymentEntity, StoreError> {
         let conn = self.get_conn()?;
-        detail::deployment_entity(&conn, site)
+        detail::deployment_entity(&self.logger, &conn, site)
     }
 
     // Remove the data and metadata for the deployment `site`. This operation
@@ -588,7 +588,9 @@ impl DeploymentStore {
         ids: Vec<String>,
     ) -> Result<Vec<DeploymentDetail>, StoreError> {
         let conn = self.get_conn()?;
-        conn.transaction(|| -> Result<_, StoreError> { detail::deployment_details(&conn, ids) })
+        conn.transaction(|| -> Result<_, StoreError> {
+            detail::deployment_details(&self.logger, &conn, ids)
+        })
     }
 
     pub(crate) fn deployment_statuses(
@@ -597,7 +599,7 @@ impl DeploymentStore {
     ) -> Result<Vec<status::Info>, StoreError> {
         let conn = self.get_conn()?;
         conn.transaction(|| -> Result<Vec<status::Info>, StoreError> {
-            detail::deployment_statuses(&conn, sites)
+            detail::deployment_statuses(&self.logger, &conn, sites, detail::StatusOrder::Unordered)
         })
     }
 
@@ -2,28 +2,45 @@
 //!
 // For git_testament_macros
 #![allow(unused_macros)]
+use anyhow::Context;
+use diesel::connection::SimpleConnection;
 use diesel::dsl;
 use diesel::prelude::{
-    ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, PgConnection,
-    QueryDsl, RunQueryDsl,
+    Connection, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension,
+    PgConnection, QueryDsl, RunQueryDsl, TextExpressionMethods,
 };
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Bool, Integer, Text};
 use diesel_derives::Associations;
 use git_testament::{git_testament, git_testament_macros};
-use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity};
+use graph::components::store::DeploymentId as GraphDeploymentId;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, SubgraphManifestEntity};
 use graph::prelude::{
-    bigdecimal::ToPrimitive, BigDecimal, BlockPtr, DeploymentHash, StoreError,
-    SubgraphDeploymentEntity,
+    bigdecimal::ToPrimitive, debug, error, hex, trace, BigDecimal, BlockPtr, DeploymentHash,
+    Logger, Serialize, StoreError, SubgraphDeploymentEntity,
 };
+use graph::util::backoff::ExponentialBackoff;
 use graph::{constraint_violation, data::subgraph::status, prelude::web3::types::H256};
 use itertools::Itertools;
+use stable_hash_legacy::crypto::SetHasher;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::{ops::Bound, sync::Arc};
+use std::{ops::Bound, ops::Deref, sync::Arc, time::Duration};
 
+use crate::connection_pool::ConnectionPool;
 use crate::deployment::{
     graph_node_versions, subgraph_deployment, subgraph_error, subgraph_manifest,
     SubgraphHealth as HealthType,
 };
-use crate::primary::{DeploymentId, Site};
+use crate::primary::{deployment_schemas, subgraph_deployment_assignment, DeploymentId, Site};
+use crate::Shard;
+
+// Every shard has a copy of the assignments and deployment schemas from
+// the primary
+allow_tables_to_appear_in_same_query!(subgraph_deployment, subgraph_deployment_assignment);
+allow_tables_to_appear_in_same_query!(subgraph_deployment, deployment_schemas);
 
 git_testament_macros!(version);
 git_testament!(TESTAMENT);
@@ -35,7 +52,7 @@ const CARGO_PKG_VERSION_PATCH: &str = env!("CARGO_PKG_VERSION_PATCH");
 
 type Bytes = Vec<u8>;
 
-#[derive(Queryable, QueryableByName)]
+#[derive(Clone, Queryable, QueryableByName)]
 #[table_name = "subgraph_deployment"]
 // We map all fields to make loading `Detail` with diesel easier, but we
 // don't need all the fields
@@ -63,6 +80,289 @@ pub struct DeploymentDetail {
     current_reorg_depth: i32,
     max_reorg_depth: i32,
     firehose_cursor: Option<String>,
+    /// The sequence number of the latest change to the deployment; see
+    /// `deployment_details_changed_since`
+    pub change_seq: i64,
+}
+
+impl DeploymentDetail {
+    /// The latest block that the deployment has processed
+    pub fn latest_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        block(
+            &self.deployment,
+            "latest_ethereum_block",
+            self.latest_ethereum_block_hash.clone(),
+            self.latest_ethereum_block_number.clone(),
+        )
+        .map(|block| block.map(|block| block.to_ptr()))
+    }
+
+    /// The earliest block of the deployment
+    pub fn earliest_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        block(
+            &self.deployment,
+            "earliest_ethereum_block",
+            self.earliest_ethereum_block_hash.clone(),
+            self.earliest_ethereum_block_number.clone(),
+        )
+        .map(|block| block.map(|block| block.to_ptr()))
+    }
+
+    /// Classify the deployment by its health and how far it has synced.
+    /// A failed deployment is `Failed` and an unhealthy one `Unhealthy`
+    /// regardless of how far they have synced; only for healthy
+    /// deployments does the sync state matter
+    pub fn sync_status(&self) -> SyncStatus {
+        if self.failed || matches!(self.health, HealthType::Failed) {
+            SyncStatus::Failed
+        } else if matches!(self.health, HealthType::Unhealthy) {
+            SyncStatus::Unhealthy
+        } else if self.latest_ethereum_block_number.is_none() {
+            SyncStatus::NeverStarted
+        } else if self.synced {
+            SyncStatus::Synced
+        } else {
+            SyncStatus::Syncing
+        }
+    }
+
+    /// The last block at which the deployment was healthy
+    pub fn last_healthy_block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        block(
+            &self.deployment,
+            "last_healthy_ethereum_block",
+            self.last_healthy_ethereum_block_hash.clone(),
+            self.last_healthy_ethereum_block_number.clone(),
+        )
+        .map(|block| block.map(|block| block.to_ptr()))
+    }
+
+    /// Return how many blocks the latest block of the deployment is behind
+    /// `head`, or `None` if the deployment has not processed any blocks.
+    /// The result is negative if the deployment is ahead of `head`, which
+    /// can happen when `head` is stale
+    pub fn blocks_behind(&self, head: &BigDecimal) -> Option<BigDecimal> {
+        self.latest_ethereum_block_number
+            .as_ref()
+            .map(|latest| head.clone() - latest.clone())
+    }
+
+    /// Return whether the deployment has indexed up to its graft block, or
+    /// `None` if it is not grafted. A deployment whose latest or graft block
+    /// number is not set also gives `None` since there is nothing to
+    /// compare
+    pub fn graft_complete(&self) -> Option<bool> {
+        self.graft_base.as_ref()?;
+        let latest = self.latest_ethereum_block_number.as_ref()?;
+        let graft = self.graft_block_number.as_ref()?;
+        Some(latest >= graft)
+    }
+
+    /// Return `true` if the latest block of the deployment has the same
+    /// hash and number as `ptr`, and `false` if it is different or the
+    /// deployment has not processed any blocks yet
+    pub fn latest_matches(&self, ptr: &BlockPtr) -> Result<bool, StoreError> {
+        match (
+            &self.latest_ethereum_block_hash,
+            &self.latest_ethereum_block_number,
+        ) {
+            (Some(hash), Some(number)) => {
+                if hash.len() != H256::len_bytes() {
+                    return Err(constraint_violation!(
+                        "the hash of latest_ethereum_block for {} must be {} bytes long, \
+                         but is {} bytes long",
+                        self.deployment,
+                        H256::len_bytes(),
+                        hash.len()
+                    ));
+                }
+                // Compare numbers as `BigDecimal` so that a stored number
+                // that does not fit into a block number simply doesn't match
+                Ok(hash.as_slice() == ptr.hash_slice() && *number == BigDecimal::from(ptr.number))
+            }
+            (None, None) => Ok(false),
+            _ => Err(constraint_violation!(
+                "the hash and number of latest_ethereum_block for {} must either \
+                 both be null or both have a value",
+                self.deployment
+            )),
+        }
+    }
+}
+
+/// How far a deployment has synced, taking its health into account. See
+/// `DeploymentDetail::sync_status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncStatus {
+    /// The deployment has not processed any blocks yet
+    NeverStarted,
+    Syncing,
+    Synced,
+    /// The deployment has non-fatal errors
+    Unhealthy,
+    Failed,
+}
+
+/// Build a `DeploymentDetail` without loading it from the database; this
+/// is only meant for tests. Unless changed with one of the setters, the
+/// deployment is healthy, synced, has no entities, and has processed
+/// exactly the block with number 0 and hash `0x00..00`
+#[cfg(any(test, feature = "test-support"))]
+pub struct DeploymentDetailBuilder {
+    detail: DeploymentDetail,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl DeploymentDetailBuilder {
+    pub fn new(id: DeploymentId, deployment: &str) -> Self {
+        let genesis = BlockPtr::from((H256::zero(), 0u64));
+        let (hash, number) = block_columns(Some(&genesis));
+        let detail = DeploymentDetail {
+            id,
+            deployment: deployment.to_string(),
+            failed: false,
+            health: HealthType::Healthy,
+            synced: true,
+            fatal_error: None,
+            non_fatal_errors: vec![],
+            earliest_ethereum_block_hash: hash.clone(),
+            earliest_ethereum_block_number: number.clone(),
+            latest_ethereum_block_hash: hash,
+            latest_ethereum_block_number: number,
+            last_healthy_ethereum_block_hash: None,
+            last_healthy_ethereum_block_number: None,
+            entity_count: BigDecimal::from(0),
+            graft_base: None,
+            graft_block_hash: None,
+            graft_block_number: None,
+            debug_fork: None,
+            reorg_count: 0,
+            current_reorg_depth: 0,
+            max_reorg_depth: 0,
+            firehose_cursor: None,
+            change_seq: 0,
+        };
+        DeploymentDetailBuilder { detail }
+    }
+
+    /// Set the health; this also marks the deployment as failed if
+    /// `health` is `Failed`
+    pub fn health(mut self, health: SubgraphHealth) -> Self {
+        self.detail.health = health.into();
+        self.detail.failed = health.is_failed();
+        self
+    }
+
+    pub fn synced(mut self, synced: bool) -> Self {
+        self.detail.synced = synced;
+        self
+    }
+
+    pub fn earliest_block(mut self, ptr: Option<&BlockPtr>) -> Self {
+        let (hash, number) = block_columns(ptr);
+        self.detail.earliest_ethereum_block_hash = hash;
+        self.detail.earliest_ethereum_block_number = number;
+        self
+    }
+
+    pub fn latest_block(mut self, ptr: Option<&BlockPtr>) -> Self {
+        let (hash, number) = block_columns(ptr);
+        self.detail.latest_ethereum_block_hash = hash;
+        self.detail.latest_ethereum_block_number = number;
+        self
+    }
+
+    pub fn last_healthy_block(mut self, ptr: Option<&BlockPtr>) -> Self {
+        let (hash, number) = block_columns(ptr);
+        self.detail.last_healthy_ethereum_block_hash = hash;
+        self.detail.last_healthy_ethereum_block_number = number;
+        self
+    }
+
+    /// Make the deployment a graft of `base` at block `ptr`
+    pub fn graft(mut self, base: &str, ptr: &BlockPtr) -> Self {
+        let (hash, number) = block_columns(Some(ptr));
+        self.detail.graft_base = Some(base.to_string());
+        self.detail.graft_block_hash = hash;
+        self.detail.graft_block_number = number;
+        self
+    }
+
+    pub fn entity_count(mut self, count: u64) -> Self {
+        self.detail.entity_count = BigDecimal::from(count);
+        self
+    }
+
+    pub fn build(self) -> DeploymentDetail {
+        self.detail
+    }
+}
+
+/// A serializable snapshot of a `DeploymentDetail`. Block hashes are
+/// encoded as `0x`-prefixed hex strings, and block numbers and the entity
+/// count as decimal strings so that they do not lose precision when they
+/// are read by clients that use floating point numbers for JSON numbers
+#[derive(Clone, Debug, Serialize)]
+pub struct DeploymentDetailSnapshot {
+    pub id: GraphDeploymentId,
+    pub deployment: String,
+    pub failed: bool,
+    pub health: String,
+    pub synced: bool,
+    pub fatal_error: Option<String>,
+    pub non_fatal_errors: Vec<String>,
+    pub earliest_ethereum_block_hash: Option<String>,
+    pub earliest_ethereum_block_number: Option<String>,
+    pub latest_ethereum_block_hash: Option<String>,
+    pub latest_ethereum_block_number: Option<String>,
+    pub last_healthy_ethereum_block_hash: Option<String>,
+    pub last_healthy_ethereum_block_number: Option<String>,
+    pub entity_count: String,
+    pub graft_base: Option<String>,
+    pub graft_block_hash: Option<String>,
+    pub graft_block_number: Option<String>,
+    pub debug_fork: Option<String>,
+    pub reorg_count: i32,
+    pub current_reorg_depth: i32,
+    pub max_reorg_depth: i32,
+    pub firehose_cursor: Option<String>,
+}
+
+impl From<&DeploymentDetail> for DeploymentDetailSnapshot {
+    fn from(detail: &DeploymentDetail) -> Self {
+        fn hash(hash: &Option<Bytes>) -> Option<String> {
+            hash.as_ref().map(|hash| format!("0x{}", hex::encode(hash)))
+        }
+
+        fn number(number: &Option<BigDecimal>) -> Option<String> {
+            number.as_ref().map(|number| number.to_string())
+        }
+
+        DeploymentDetailSnapshot {
+            id: detail.id.into(),
+            deployment: detail.deployment.clone(),
+            failed: detail.failed,
+            health: SubgraphHealth::from(detail.health).as_str().to_string(),
+            synced: detail.synced,
+            fatal_error: detail.fatal_error.clone(),
+            non_fatal_errors: detail.non_fatal_errors.clone(),
+            earliest_ethereum_block_hash: hash(&detail.earliest_ethereum_block_hash),
+            earliest_ethereum_block_number: number(&detail.earliest_ethereum_block_number),
+            latest_ethereum_block_hash: hash(&detail.latest_ethereum_block_hash),
+            latest_ethereum_block_number: number(&detail.latest_ethereum_block_number),
+            last_healthy_ethereum_block_hash: hash(&detail.last_healthy_ethereum_block_hash),
+            last_healthy_ethereum_block_number: number(&detail.last_healthy_ethereum_block_number),
+            entity_count: detail.entity_count.to_string(),
+            graft_base: detail.graft_base.clone(),
+            graft_block_hash: hash(&detail.graft_block_hash),
+            graft_block_number: number(&detail.graft_block_number),
+            debug_fork: detail.debug_fork.clone(),
+            reorg_count: detail.reorg_count,
+            current_reorg_depth: detail.current_reorg_depth,
+            max_reorg_depth: detail.max_reorg_depth,
+            firehose_cursor: detail.firehose_cursor.clone(),
+        }
+    }
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -99,6 +399,99 @@ impl ErrorDetail {
             .optional()
             .map_err(StoreError::from)
     }
+
+    /// The range of blocks for which this error is active
+    pub fn range(&self) -> BlockRange {
+        BlockRange::from(self.block_range)
+    }
+}
+
+/// The block range of an error, with unbounded ends mapped to `None`.
+/// `start` is the first block in the range, and `end` is the first block
+/// after the range, i.e., `end` is exclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct BlockRange {
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+}
+
+impl From<(Bound<i32>, Bound<i32>)> for BlockRange {
+    fn from(range: (Bound<i32>, Bound<i32>)) -> Self {
+        let start = match range.0 {
+            Bound::Included(nr) => Some(nr),
+            Bound::Excluded(nr) => Some(nr + 1),
+            Bound::Unbounded => None,
+        };
+        let end = match range.1 {
+            Bound::Included(nr) => Some(nr + 1),
+            Bound::Excluded(nr) => Some(nr),
+            Bound::Unbounded => None,
+        };
+        BlockRange { start, end }
+    }
+}
+
+/// A row in `subgraph_error` that has not been written yet
+#[derive(Insertable, Clone, Debug)]
+#[table_name = "subgraph_error"]
+pub struct NewErrorDetail {
+    id: String,
+    subgraph_id: String,
+    message: String,
+    block_hash: Option<Bytes>,
+    handler: Option<String>,
+    deterministic: bool,
+    block_range: (Bound<i32>, Bound<i32>),
+}
+
+impl NewErrorDetail {
+    /// The id of the error, which is derived from its contents
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Write the error. Does nothing if an error with the same id already
+    /// exists
+    pub fn insert(&self, conn: &PgConnection) -> Result<(), StoreError> {
+        use subgraph_error as e;
+
+        diesel::insert_into(e::table)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+impl From<&SubgraphError> for NewErrorDetail {
+    fn from(error: &SubgraphError) -> Self {
+        let id = hex::encode(&stable_hash_legacy::utils::stable_hash::<SetHasher, _>(
+            error,
+        ));
+        // This is the inverse of `first_block_in_range`: an error without a
+        // block pointer gets the `UNVERSIONED_RANGE`, and any other error
+        // starts at its block. The upper bound is always unbounded; it only
+        // gets set when the error is reverted
+        let block_range = match &error.block_ptr {
+            None => crate::block_range::UNVERSIONED_RANGE,
+            Some(ptr) => (
+                Bound::Included(crate::block_range::block_number(ptr)),
+                Bound::Unbounded,
+            ),
+        };
+        NewErrorDetail {
+            id,
+            subgraph_id: error.subgraph_id.to_string(),
+            message: error.message.clone(),
+            block_hash: error
+                .block_ptr
+                .as_ref()
+                .map(|ptr| ptr.hash_slice().to_vec()),
+            handler: error.handler.clone(),
+            deterministic: error.deterministic,
+            block_range,
+        }
+    }
 }
 
 impl TryFrom<ErrorDetail> for SubgraphError {
@@ -125,6 +518,7 @@ impl TryFrom<ErrorDetail> for SubgraphError {
             (Some(number), Some(hash)) => Some(BlockPtr::from((hash, number as u64))),
             _ => None,
         };
+        let block_hash_only = block_hash;
         let subgraph_id = DeploymentHash::new(subgraph_id).map_err(|id| {
             StoreError::ConstraintViolation(format!("invalid subgraph id `{}` in fatal error", id))
         })?;
@@ -134,10 +528,323 @@ impl TryFrom<ErrorDetail> for SubgraphError {
             block_ptr,
             handler,
             deterministic,
+            block_hash_only,
         })
     }
 }
 
+/// A `SubgraphError` together with the `vid` of the row it was read from
+/// and the range of blocks for which it is active. Since `vid` only ever
+/// increases, it can be used to page through the errors of a subgraph
+#[derive(Clone, Debug)]
+pub struct SubgraphErrorWithVid {
+    pub vid: i64,
+    pub range: BlockRange,
+    pub error: SubgraphError,
+}
+
+impl TryFrom<ErrorDetail> for SubgraphErrorWithVid {
+    type Error = StoreError;
+
+    fn try_from(value: ErrorDetail) -> Result<Self, Self::Error> {
+        let vid = value.vid;
+        let range = value.range();
+        let error = SubgraphError::try_from(value)?;
+        Ok(SubgraphErrorWithVid { vid, range, error })
+    }
+}
+
+/// Return the errors for `subgraph_id` that were recorded after the error
+/// with `vid`, ordered by `vid`
+pub fn errors_since_vid(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    vid: i64,
+) -> Result<Vec<SubgraphErrorWithVid>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .filter(e::vid.gt(vid))
+        .order_by(e::vid.asc())
+        .load::<ErrorDetail>(conn)?
+        .into_iter()
+        .map(SubgraphErrorWithVid::try_from)
+        .collect()
+}
+
+/// Return at most `limit` errors for `subgraph_id` that were recorded after
+/// the error with `after_vid`, ordered by `vid`. To page through all
+/// errors, pass the `vid` of the last error in the result as `after_vid`
+/// for the next page
+pub fn errors_after_vid(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    after_vid: i64,
+    limit: i64,
+) -> Result<Vec<SubgraphErrorWithVid>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .filter(e::vid.gt(after_vid))
+        .order_by(e::vid.asc())
+        .limit(limit)
+        .load::<ErrorDetail>(conn)?
+        .into_iter()
+        .map(SubgraphErrorWithVid::try_from)
+        .collect()
+}
+
+/// Return the errors for `subgraph_id` that are deterministic if
+/// `deterministic` is `true`, and the ones that are not when it is `false`
+pub fn errors_by_determinism(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    deterministic: bool,
+) -> Result<Vec<SubgraphError>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .filter(e::deterministic.eq(deterministic))
+        .order_by(e::vid.asc())
+        .load::<ErrorDetail>(conn)?
+        .into_iter()
+        .map(SubgraphError::try_from)
+        .collect()
+}
+
+/// Return all deterministic errors that `subgraph_id` ever encountered,
+/// ordered by the block at which they happened. Only the latest of these
+/// is linked from `subgraph_deployment.fatal_error`, but the earlier ones
+/// stay around when the subgraph recovers from a failure, e.g., because
+/// it was rewound and restarted
+pub fn fatal_error_history(
+    conn: &PgConnection,
+    subgraph_id: &str,
+) -> Result<Vec<SubgraphError>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .filter(e::deterministic.eq(true))
+        .order_by((dsl::sql::<Integer>("lower(block_range)"), e::vid.asc()))
+        .load::<ErrorDetail>(conn)?
+        .into_iter()
+        .map(SubgraphError::try_from)
+        .collect()
+}
+
+/// Return the first error that was ever recorded for `subgraph_id`, i.e.,
+/// the one with the smallest `vid`, or `None` if the subgraph never had an
+/// error
+pub fn first_error(
+    conn: &PgConnection,
+    subgraph_id: &str,
+) -> Result<Option<SubgraphError>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .order_by(e::vid.asc())
+        .first::<ErrorDetail>(conn)
+        .optional()?
+        .map(SubgraphError::try_from)
+        .transpose()
+}
+
+/// Return all errors for `subgraph_id` whose block range overlaps the
+/// blocks from `from` to `to`. As with Postgres' `int4range`, `from` is
+/// inclusive and `to` is exclusive, i.e., the errors that were current at
+/// any block `b` with `from <= b < to` are returned, ordered by `vid`
+pub fn errors_in_block_range(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    from: i32,
+    to: i32,
+) -> Result<Vec<SubgraphError>, StoreError> {
+    const QUERY: &str = "select * from subgraphs.subgraph_error \
+                          where subgraph_id = $1 \
+                            and block_range && int4range($2, $3) \
+                          order by vid";
+
+    sql_query(QUERY)
+        .bind::<Text, _>(subgraph_id)
+        .bind::<Integer, _>(from)
+        .bind::<Integer, _>(to)
+        .load::<ErrorDetail>(conn)?
+        .into_iter()
+        .map(SubgraphError::try_from)
+        .collect()
+}
+
+/// An `ErrorDetail` together with the total number of rows that the query
+/// that produced it would return without `limit` and `offset`
+#[derive(QueryableByName)]
+struct ErrorDetailWithTotal {
+    #[diesel(embed)]
+    detail: ErrorDetail,
+    #[sql_type = "BigInt"]
+    total: i64,
+}
+
+/// Return at most `limit` errors for `subgraph_id`, skipping the first
+/// `offset` ones by `vid`, together with the total number of errors for
+/// `subgraph_id`. Page and total come from the same query and are
+/// therefore consistent with each other
+pub fn errors_page(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    offset: i64,
+    limit: i64,
+) -> Result<(Vec<SubgraphError>, i64), StoreError> {
+    use subgraph_error as e;
+
+    const QUERY: &str = "select *, count(*) over () as total \
+                           from subgraphs.subgraph_error \
+                          where subgraph_id = $1 \
+                          order by vid \
+                         offset $2 limit $3";
+
+    let rows = sql_query(QUERY)
+        .bind::<Text, _>(subgraph_id)
+        .bind::<BigInt, _>(offset)
+        .bind::<BigInt, _>(limit)
+        .load::<ErrorDetailWithTotal>(conn)?;
+
+    let total = match rows.first() {
+        Some(row) => row.total,
+        // The page is empty, either because there are no errors or
+        // because `offset` is past the end; only in the latter case is
+        // there anything to count
+        None if offset > 0 => e::table
+            .filter(e::subgraph_id.eq(subgraph_id))
+            .count()
+            .get_result::<i64>(conn)?,
+        None => 0,
+    };
+    let errors = rows
+        .into_iter()
+        .map(|row| SubgraphError::try_from(row.detail))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((errors, total))
+}
+
+/// Return the error for `subgraph_id` that was current at `block`, i.e.,
+/// whose block range contains `block`. If there are several such errors,
+/// return the one that was recorded last
+pub fn error_at_block(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    block: i32,
+) -> Result<Option<SubgraphError>, StoreError> {
+    const QUERY: &str = "select * from subgraphs.subgraph_error \
+                          where subgraph_id = $1 \
+                            and block_range @> $2 \
+                          order by vid desc \
+                          limit 1";
+
+    sql_query(QUERY)
+        .bind::<Text, _>(subgraph_id)
+        .bind::<Integer, _>(block)
+        .get_result::<ErrorDetail>(conn)
+        .optional()?
+        .map(SubgraphError::try_from)
+        .transpose()
+}
+
+/// Delete the errors for `subgraph_id` that were resolved before `block`,
+/// i.e., whose block range ends at or before `block`, and return how many
+/// errors were deleted. An error that is the fatal error of a deployment
+/// is never deleted
+pub fn delete_errors_before_block(
+    conn: &PgConnection,
+    subgraph_id: &str,
+    block: i32,
+) -> Result<usize, StoreError> {
+    // Errors that are still current have an unbounded block range, and
+    // `upper(block_range)` is null for them
+    const QUERY: &str = "delete from subgraphs.subgraph_error e \
+                          where e.subgraph_id = $1 \
+                            and upper(e.block_range) <= $2 \
+                            and not exists (select 1 \
+                                              from subgraphs.subgraph_deployment d \
+                                             where d.fatal_error = e.id)";
+
+    sql_query(QUERY)
+        .bind::<Text, _>(subgraph_id)
+        .bind::<Integer, _>(block)
+        .execute(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the `vid` of all errors whose `subgraph_id` does not belong to
+/// any deployment, ordered by `vid`. Deleting a deployment cascades to its
+/// errors, so such errors only exist if that cascade was bypassed, e.g., by
+/// manually editing the database
+pub fn orphaned_errors(conn: &PgConnection) -> Result<Vec<i64>, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    e::table
+        .filter(dsl::not(dsl::exists(
+            d::table.filter(d::deployment.eq(e::subgraph_id)),
+        )))
+        .select(e::vid)
+        .order_by(e::vid)
+        .load::<i64>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Delete all errors that `orphaned_errors` would return, and return how
+/// many errors were deleted
+pub fn delete_orphaned_errors(conn: &PgConnection) -> Result<usize, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    diesel::delete(e::table.filter(dsl::not(dsl::exists(
+        d::table.filter(d::deployment.eq(e::subgraph_id)),
+    ))))
+    .execute(conn)
+    .map_err(StoreError::from)
+}
+
+/// Return the number of errors for each of `subgraph_ids`. Subgraphs
+/// that have no errors are not included in the result; callers should
+/// treat them as having 0 errors
+pub fn error_counts(
+    conn: &PgConnection,
+    subgraph_ids: &[&str],
+) -> Result<HashMap<String, i64>, StoreError> {
+    use subgraph_error as e;
+
+    let counts = e::table
+        .filter(e::subgraph_id.eq(dsl::any(subgraph_ids)))
+        .select((e::subgraph_id, dsl::sql("count(*)")))
+        .group_by(e::subgraph_id)
+        .load::<(String, i64)>(conn)?;
+    Ok(counts.into_iter().collect())
+}
+
+/// Return the number of errors for `subgraph_id` for each handler, with
+/// the handler with the most errors first. Errors that were not raised in
+/// a handler are counted under `None`
+pub fn error_counts_by_handler(
+    conn: &PgConnection,
+    subgraph_id: &str,
+) -> Result<Vec<(Option<String>, i64)>, StoreError> {
+    use subgraph_error as e;
+
+    e::table
+        .filter(e::subgraph_id.eq(subgraph_id))
+        .select((e::handler, dsl::sql::<BigInt>("count(*)")))
+        .group_by(e::handler)
+        .order_by((dsl::sql::<BigInt>("count(*)").desc(), e::handler))
+        .load::<(Option<String>, i64)>(conn)
+        .map_err(StoreError::from)
+}
+
 pub(crate) fn block(
     id: &str,
     name: &str,
@@ -146,6 +853,15 @@ pub(crate) fn block(
 ) -> Result<Option<status::EthereumBlock>, StoreError> {
     match (&hash, &number) {
         (Some(hash), Some(number)) => {
+            if hash.len() != H256::len_bytes() {
+                return Err(constraint_violation!(
+                    "the hash of {} for {} must be {} bytes long, but is {} bytes long",
+                    name,
+                    id,
+                    H256::len_bytes(),
+                    hash.len()
+                ));
+            }
             let hash = H256::from_slice(hash.as_slice());
             let number = number.to_u64().ok_or_else(|| {
                 constraint_violation!(
@@ -170,16 +886,51 @@ pub(crate) fn block(
     }
 }
 
+/// Turn `ptr` into the hash and number columns that `block` reads; this
+/// is the representation in which block pointers need to be written
+pub fn block_columns(ptr: Option<&BlockPtr>) -> (Option<Bytes>, Option<BigDecimal>) {
+    match ptr {
+        Some(ptr) => (
+            Some(ptr.hash_slice().to_vec()),
+            Some(BigDecimal::from(ptr.number)),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Return the network of the site with `id` among `sites`. It is an error
+/// if `sites` does not contain a site with that id.
+///
+/// The site is looked up by its id and not by its deployment hash since
+/// the same hash can have several sites in different shards, e.g., while
+/// a deployment is being copied, and only the id identifies a site
+pub fn network_for_deployment(sites: &[Arc<Site>], id: DeploymentId) -> Result<&str, StoreError> {
+    sites
+        .iter()
+        .find(|site| site.id == id)
+        .map(|site| site.network.as_str())
+        .ok_or_else(|| constraint_violation!("missing site for deployment with id {}", id))
+}
+
+/// Build the status for `detail`. The status has one `ChainInfo` for each
+/// of the `networks` that the deployment's data sources index; if
+/// `networks` is empty, it has one `ChainInfo` for the network of the
+/// deployment's site in `sites`.
+///
+/// Since we only store one set of block pointers per deployment, all
+/// chains report the same earliest and latest block; tracking blocks per
+/// chain will require changes to the `subgraph_deployment` table
 pub(crate) fn info_from_details(
     detail: DeploymentDetail,
     fatal: Option<ErrorDetail>,
     non_fatal: Vec<ErrorDetail>,
     sites: &[Arc<Site>],
+    networks: &[String],
 ) -> Result<status::Info, StoreError> {
     let DeploymentDetail {
         id,
         deployment,
-        failed: _,
+        failed,
         health,
         synced,
         fatal_error: _,
@@ -195,14 +946,12 @@ pub(crate) fn info_from_details(
         ..
     } = detail;
 
-    let site = sites
-        .iter()
-        .find(|site| site.deployment.as_str() == deployment)
-        .ok_or_else(|| constraint_violation!("missing site for subgraph `{}`", deployment))?;
+    let networks: Vec<String> = if networks.is_empty() {
+        vec![network_for_deployment(sites, id)?.to_string()]
+    } else {
+        networks.iter().unique().cloned().collect()
+    };
 
-    // This needs to be filled in later since it lives in a
-    // different shard
-    let chain_head_block = None;
     let earliest_block = block(
         &deployment,
         "earliest_ethereum_block",
@@ -216,12 +965,17 @@ pub(crate) fn info_from_details(
         latest_ethereum_block_number,
     )?;
     let health = health.into();
-    let chain = status::ChainInfo {
-        network: site.network.clone(),
-        chain_head_block,
-        earliest_block,
-        latest_block,
-    };
+    let chains = networks
+        .into_iter()
+        .map(|network| status::ChainInfo {
+            network,
+            // This needs to be filled in later since it lives in a
+            // different shard
+            chain_head_block: None,
+            earliest_block: earliest_block.clone(),
+            latest_block: latest_block.clone(),
+        })
+        .collect();
     let entity_count = entity_count.to_u64().ok_or_else(|| {
         constraint_violation!(
             "the entityCount for {} is not representable as a u64",
@@ -240,71 +994,1061 @@ pub(crate) fn info_from_details(
         subgraph: deployment,
         synced,
         health,
+        failed,
         fatal_error,
         non_fatal_errors,
-        chains: vec![chain],
+        chains,
         entity_count,
         node: None,
     })
 }
 
-/// Return the details for `deployments`
-pub(crate) fn deployment_details(
-    conn: &PgConnection,
-    deployments: Vec<String>,
-) -> Result<Vec<DeploymentDetail>, StoreError> {
-    use subgraph_deployment as d;
+/// Check that the block pointers of `detail` are consistent with each
+/// other, i.e., that `earliest <= last_healthy <= latest` and that the
+/// graft block is not after the latest block. Block pointers that are
+/// null are not checked
+pub fn validate_block_pointers(detail: &DeploymentDetail) -> Result<(), StoreError> {
+    fn check(
+        deployment: &str,
+        (lower_name, lower): (&str, &Option<BigDecimal>),
+        (upper_name, upper): (&str, &Option<BigDecimal>),
+    ) -> Result<(), StoreError> {
+        match (lower, upper) {
+            (Some(lower), Some(upper)) if lower > upper => Err(constraint_violation!(
+                "the {} {} of {} is after its {} {}",
+                lower_name,
+                lower,
+                deployment,
+                upper_name,
+                upper
+            )),
+            _ => Ok(()),
+        }
+    }
 
-    // Empty deployments means 'all of them'
-    let details = if deployments.is_empty() {
-        d::table.load::<DeploymentDetail>(conn)?
-    } else {
-        d::table
-            .filter(d::deployment.eq_any(&deployments))
-            .load::<DeploymentDetail>(conn)?
-    };
-    Ok(details)
+    let earliest = (
+        "earliest_ethereum_block",
+        &detail.earliest_ethereum_block_number,
+    );
+    let last_healthy = (
+        "last_healthy_ethereum_block",
+        &detail.last_healthy_ethereum_block_number,
+    );
+    let latest = (
+        "latest_ethereum_block",
+        &detail.latest_ethereum_block_number,
+    );
+    let graft = ("graft_block", &detail.graft_block_number);
+
+    check(&detail.deployment, earliest, last_healthy)?;
+    check(&detail.deployment, last_healthy, latest)?;
+    check(&detail.deployment, earliest, latest)?;
+    check(&detail.deployment, graft, latest)
 }
 
-pub(crate) fn deployment_statuses(
-    conn: &PgConnection,
-    sites: &[Arc<Site>],
-) -> Result<Vec<status::Info>, StoreError> {
-    use subgraph_deployment as d;
-    use subgraph_error as e;
+/// Check that the current reorg depth of `detail` does not exceed its
+/// maximum reorg depth
+pub fn check_reorg_within_bounds(detail: &DeploymentDetail) -> Result<(), StoreError> {
+    if detail.current_reorg_depth > detail.max_reorg_depth {
+        return Err(StoreError::ReorgDepthExceeded {
+            deployment: detail.deployment.clone(),
+            current: detail.current_reorg_depth,
+            max: detail.max_reorg_depth,
+        });
+    }
+    Ok(())
+}
 
-    // First, we fetch all deployment information along with any fatal errors.
-    // Subsequently, we fetch non-fatal errors and we group them by deployment
-    // ID.
+/// Return `true` if the latest block of the deployment is not the block
+/// `chain_hash_at_latest` that the chain has at the same block number, i.e.,
+/// if the chain had a reorg that the deployment has not processed yet. A
+/// deployment that has not processed any blocks can not be affected by a
+/// reorg
+pub fn reorg_detected(
+    detail: &DeploymentDetail,
+    chain_hash_at_latest: H256,
+) -> Result<bool, StoreError> {
+    Ok(detail
+        .latest_block_ptr()?
+        .map(|latest| latest.hash_as_h256() != chain_hash_at_latest)
+        .unwrap_or(false))
+}
 
-    let details_with_fatal_error = {
-        let join = e::table.on(e::id.nullable().eq(d::fatal_error));
+/// Check that the deployment can be pruned so that it only keeps history
+/// from `target_block` onwards. The `target_block` must be before the
+/// latest block of the deployment, and, for grafted deployments, can not
+/// be before the graft block
+pub fn prune_safe_to(detail: &DeploymentDetail, target_block: i32) -> Result<(), StoreError> {
+    let target = BigDecimal::from(target_block);
 
-        // Empty deployments means 'all of them'
-        if sites.is_empty() {
-            d::table
-                .left_outer_join(join)
-                .load::<(DeploymentDetail, Option<ErrorDetail>)>(conn)?
-        } else {
-            d::table
-                .left_outer_join(join)
-                .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
-                .load::<(DeploymentDetail, Option<ErrorDetail>)>(conn)?
+    let latest = detail
+        .latest_ethereum_block_number
+        .as_ref()
+        .ok_or_else(|| {
+            constraint_violation!(
+                "can not prune {} since it has not processed any blocks yet",
+                detail.deployment
+            )
+        })?;
+    if &target >= latest {
+        return Err(constraint_violation!(
+            "can not prune {} to block {} since it is not before its latest block {}",
+            detail.deployment,
+            target_block,
+            latest
+        ));
+    }
+    if let Some(graft) = &detail.graft_block_number {
+        if &target < graft {
+            return Err(constraint_violation!(
+                "can not prune {} to block {} since it is before the graft block {}",
+                detail.deployment,
+                target_block,
+                graft
+            ));
         }
-    };
+    }
+    Ok(())
+}
 
-    let mut non_fatal_errors = {
-        let join = e::table.on(e::id.eq(dsl::any(d::non_fatal_errors)));
+/// Return how far the deployment has progressed from its earliest block
+/// towards `chain_head` as a ratio between 0.0 and 1.0. The result is
+/// `None` if the deployment is missing its earliest or latest block, or if
+/// the earliest block is the chain head. Since the chain head might be
+/// stale, the result is clamped to that range
+pub fn sync_progress(detail: &DeploymentDetail, chain_head: &BlockPtr) -> Option<f64> {
+    let earliest = detail.earliest_ethereum_block_number.as_ref()?;
+    let latest = detail.latest_ethereum_block_number.as_ref()?;
+    let head = BigDecimal::from(chain_head.number);
 
-        if sites.is_empty() {
-            d::table
-                .inner_join(join)
-                .select((d::id, e::all_columns))
-                .load::<(DeploymentId, ErrorDetail)>(conn)?
-        } else {
-            d::table
-                .inner_join(join)
-                .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
+    // Block numbers are integers, and `ToPrimitive::to_f64` for
+    // `BigDecimal` would truncate a fractional ratio
+    let done = (latest.clone() - earliest.clone()).to_i64()?;
+    let total = (head - earliest.clone()).to_i64()?;
+    if total == 0 {
+        return None;
+    }
+    Some((done as f64 / total as f64).clamp(0.0, 1.0))
+}
+
+/// Return `true` if the deployment has not made any progress between the
+/// two snapshots `before` and `after` even though it is not synced. A
+/// deployment that has not processed any blocks is never considered
+/// stalled
+pub fn is_stalled(before: &DeploymentDetail, after: &DeploymentDetail) -> bool {
+    match (
+        &before.latest_ethereum_block_number,
+        &after.latest_ethereum_block_number,
+    ) {
+        (Some(before), Some(after_number)) => !after.synced && before == after_number,
+        _ => false,
+    }
+}
+
+/// Return by how much the entity count changed between two snapshots
+/// `before` and `after` of the same deployment
+pub fn entity_count_delta(
+    before: &DeploymentDetail,
+    after: &DeploymentDetail,
+) -> Result<i64, StoreError> {
+    fn count(detail: &DeploymentDetail) -> Result<i64, StoreError> {
+        detail.entity_count.to_i64().ok_or_else(|| {
+            constraint_violation!(
+                "the entityCount for {} is not representable as an i64",
+                detail.deployment
+            )
+        })
+    }
+
+    let (before_count, after_count) = (count(before)?, count(after)?);
+    after_count.checked_sub(before_count).ok_or_else(|| {
+        constraint_violation!(
+            "the change in entityCount for {} from {} to {} is not representable as an i64",
+            after.deployment,
+            before_count,
+            after_count
+        )
+    })
+}
+
+/// Return `true` if the entity count of `detail` is larger than `cap`. A
+/// count that is too large to fit into a `u64` is considered to exceed any
+/// cap rather than being an error; a negative count is an error
+pub fn entity_count_exceeds(detail: &DeploymentDetail, cap: u64) -> Result<bool, StoreError> {
+    match detail.entity_count.to_u64() {
+        Some(count) => Ok(count > cap),
+        None if detail.entity_count > BigDecimal::zero() => Ok(true),
+        None => Err(constraint_violation!(
+            "the entityCount for {} is not representable as a u64",
+            detail.deployment
+        )),
+    }
+}
+
+/// Return by how much the entity count stored for `detail` differs from
+/// the `actual` number of entities, as `actual - stored`. Any result other
+/// than 0 means that the stored entity count needs to be corrected
+pub fn entity_count_drift(detail: &DeploymentDetail, actual: u64) -> Result<i64, StoreError> {
+    let stored = detail.entity_count.to_u64().ok_or_else(|| {
+        constraint_violation!(
+            "the entityCount for {} is not representable as a u64",
+            detail.deployment
+        )
+    })?;
+    let drift = i128::from(actual) - i128::from(stored);
+    i64::try_from(drift).map_err(|_| {
+        constraint_violation!(
+            "the difference between the entityCount {} for {} and the actual count {} \
+             is not representable as an i64",
+            stored,
+            detail.deployment,
+            actual
+        )
+    })
+}
+
+/// The fields in which two `DeploymentDetail` for the same deployment
+/// differ. Each field is `None` if both details agree, and `(a, b)` with
+/// the values from each detail otherwise
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DetailDiff {
+    pub health: Option<(SubgraphHealth, SubgraphHealth)>,
+    pub synced: Option<(bool, bool)>,
+    pub latest_block_hash: Option<(Option<Bytes>, Option<Bytes>)>,
+    pub latest_block_number: Option<(Option<BigDecimal>, Option<BigDecimal>)>,
+    pub entity_count: Option<(BigDecimal, BigDecimal)>,
+    pub reorg_count: Option<(i32, i32)>,
+    pub current_reorg_depth: Option<(i32, i32)>,
+    pub max_reorg_depth: Option<(i32, i32)>,
+}
+
+impl DetailDiff {
+    /// Return `true` if the two details agree on all compared fields
+    pub fn is_empty(&self) -> bool {
+        self == &DetailDiff::default()
+    }
+}
+
+/// Compare the details `a` and `b`, usually of the same deployment read
+/// from two different stores, and return the fields in which they differ
+pub fn diff_details(a: &DeploymentDetail, b: &DeploymentDetail) -> DetailDiff {
+    fn diff<T: PartialEq + Clone>(a: &T, b: &T) -> Option<(T, T)> {
+        if a == b {
+            None
+        } else {
+            Some((a.clone(), b.clone()))
+        }
+    }
+
+    // Comparing `BigDecimal` with `==` compares their numeric values, so
+    // that, e.g., `10` and `10.0` are considered equal
+    DetailDiff {
+        health: diff(
+            &SubgraphHealth::from(a.health),
+            &SubgraphHealth::from(b.health),
+        ),
+        synced: diff(&a.synced, &b.synced),
+        latest_block_hash: diff(&a.latest_ethereum_block_hash, &b.latest_ethereum_block_hash),
+        latest_block_number: diff(
+            &a.latest_ethereum_block_number,
+            &b.latest_ethereum_block_number,
+        ),
+        entity_count: diff(&a.entity_count, &b.entity_count),
+        reorg_count: diff(&a.reorg_count, &b.reorg_count),
+        current_reorg_depth: diff(&a.current_reorg_depth, &b.current_reorg_depth),
+        max_reorg_depth: diff(&a.max_reorg_depth, &b.max_reorg_depth),
+    }
+}
+
+/// Sort `details` by how far each deployment lags behind the head of its
+/// chain, with the deployment that lags the most first. The network of a
+/// deployment is determined with `site_network`, and the chain head for
+/// that with `heads`. Deployments whose lag can not be determined, because
+/// we don't know their network, chain head or latest block, come last
+pub fn sort_details_by_lag(
+    details: &mut [DeploymentDetail],
+    heads: &HashMap<String, BigDecimal>,
+    site_network: impl Fn(&DeploymentDetail) -> Option<String>,
+) {
+    details.sort_by_cached_key(|detail| {
+        let lag = site_network(detail)
+            .and_then(|network| heads.get(&network))
+            .zip(detail.latest_ethereum_block_number.as_ref())
+            .map(|(head, latest)| head.clone() - latest.clone());
+        // `None` sorts before `Some`; reversing the order puts it last
+        Reverse(lag)
+    });
+}
+
+/// Return the deployment among `sites` that is not synced and lags the
+/// most behind the head of its chain, together with its lag. The chain
+/// heads are taken from `heads`, which maps network names to block
+/// numbers. Deployments whose network is not in `heads`, and deployments
+/// that have not processed any blocks yet, are not considered
+pub fn furthest_behind_unsynced(
+    conn: &PgConnection,
+    heads: &HashMap<String, BigDecimal>,
+    sites: &[Arc<Site>],
+) -> Result<Option<(DeploymentId, BigDecimal)>, StoreError> {
+    use subgraph_deployment as d;
+
+    let networks: HashMap<_, _> = sites
+        .iter()
+        .map(|site| (site.id, site.network.as_str()))
+        .collect();
+    let details = d::table
+        .filter(d::synced.eq(false))
+        .filter(d::id.eq_any(networks.keys().copied().collect::<Vec<_>>()))
+        .load::<DeploymentDetail>(conn)?;
+
+    Ok(details
+        .iter()
+        .filter_map(|detail| {
+            let head = heads.get(*networks.get(&detail.id)?)?;
+            let lag = detail.blocks_behind(head)?;
+            Some((detail.id, lag))
+        })
+        .max_by(|(_, a), (_, b)| a.cmp(b)))
+}
+
+/// Describe which deployments a query is restricted to for logging
+fn deployment_filter<'a>(deployments: impl Iterator<Item = &'a str>) -> String {
+    let mut deployments = deployments.peekable();
+    if deployments.peek().is_none() {
+        "all".to_string()
+    } else {
+        deployments.join(", ")
+    }
+}
+
+/// Log `res` if it is an error, together with the `function` that
+/// produced it
+fn log_error<T>(
+    logger: &Logger,
+    function: &str,
+    res: Result<T, StoreError>,
+) -> Result<T, StoreError> {
+    if let Err(e) = &res {
+        error!(logger, "Failed to query deployment details";
+               "function" => function,
+               "error" => e.to_string());
+    }
+    res
+}
+
+/// Check that `conn` is usable for the queries in this module. Returns
+/// `StoreError::DatabaseUnavailable` if the database can not be reached
+/// through `conn`, and `StoreError::UnknownTable` if the database does not
+/// have the `subgraphs.subgraph_deployment` table, which usually means
+/// that `conn` points at the wrong database
+pub fn preflight(conn: &PgConnection) -> Result<(), StoreError> {
+    const TABLE: &str = "subgraphs.subgraph_deployment";
+
+    diesel::select(dsl::sql::<Integer>("1"))
+        .execute(conn)
+        .map_err(|_| StoreError::DatabaseUnavailable)?;
+
+    let visible = diesel::select(dsl::sql::<Bool>(&format!(
+        "to_regclass('{}') is not null",
+        TABLE
+    )))
+    .get_result::<bool>(conn)?;
+    if !visible {
+        return Err(StoreError::UnknownTable(TABLE.to_string()));
+    }
+    Ok(())
+}
+
+const RETRY_BASE: Duration = Duration::from_millis(100);
+const RETRY_CEIL: Duration = Duration::from_secs(10);
+
+/// Messages of errors that indicate that the connection to the database
+/// was lost. Diesel reports them with `DatabaseErrorKind::__Unknown`, and
+/// we can only recognize them by their text, which only works if Postgres
+/// uses an English locale
+const CONNECTION_LOST_MESSAGES: &[&str] = &[
+    "server closed the connection unexpectedly",
+    "no connection to the server",
+    "terminating connection",
+    "could not receive data from server",
+];
+
+/// Return `true` if `error` is caused by a problem with the connection to
+/// the database rather than by the query itself, so that running the same
+/// query again on a new connection might succeed
+fn is_transient(error: &StoreError) -> bool {
+    use diesel::result::Error as E;
+
+    match error {
+        StoreError::DatabaseUnavailable => true,
+        StoreError::Unknown(e) => match e.downcast_ref::<E>() {
+            Some(E::DatabaseError(DatabaseErrorKind::UnableToSendCommand, _)) => true,
+            Some(E::DatabaseError(DatabaseErrorKind::__Unknown, info)) => {
+                let msg = info.message();
+                CONNECTION_LOST_MESSAGES
+                    .iter()
+                    .any(|lost| msg.contains(lost))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Run `f` against a connection from `get_conn`, and run it again, with
+/// exponential backoff, if getting the connection or running `f` fails
+/// with a transient database error, for at most `max_attempts` attempts
+/// overall. Each attempt uses a new connection since a connection that was
+/// lost never recovers. Any other error, like a constraint violation, is
+/// returned right away
+pub fn with_retry<T, C, G, F>(f: F, get_conn: G, max_attempts: usize) -> Result<T, StoreError>
+where
+    C: Deref<Target = PgConnection>,
+    G: Fn() -> Result<C, StoreError>,
+    F: Fn(&PgConnection) -> Result<T, StoreError>,
+{
+    let mut backoff = ExponentialBackoff::new(RETRY_BASE, RETRY_CEIL);
+    let mut attempt = 1;
+    loop {
+        match get_conn().and_then(|conn| f(&conn)) {
+            Err(e) if is_transient(&e) && attempt < max_attempts => {
+                attempt += 1;
+                backoff.sleep();
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Return the details for `deployments`
+pub(crate) fn deployment_details(
+    logger: &Logger,
+    conn: &PgConnection,
+    deployments: Vec<String>,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    debug!(logger, "Loading deployment details";
+           "deployments" => deployment_filter(deployments.iter().map(String::as_str)));
+
+    // Empty deployments means 'all of them'
+    let details = if deployments.is_empty() {
+        d::table.load::<DeploymentDetail>(conn)
+    } else {
+        d::table
+            .filter(d::deployment.eq_any(&deployments))
+            .load::<DeploymentDetail>(conn)
+    };
+    let details = log_error(
+        logger,
+        "deployment_details",
+        details.map_err(StoreError::from),
+    )?;
+
+    trace!(logger, "Loaded deployment details"; "count" => details.len());
+    Ok(details)
+}
+
+/// Like `deployment_details`, but retry up to `max_attempts` times if
+/// loading the details fails with a transient database error
+pub fn deployment_details_with_retry(
+    logger: &Logger,
+    pool: &ConnectionPool,
+    deployments: Vec<String>,
+    max_attempts: usize,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    with_retry(
+        |conn| deployment_details(logger, conn, deployments.clone()),
+        || pool.get(),
+        max_attempts,
+    )
+}
+
+/// Call `f` with the details of each deployment. The details are loaded
+/// in batches of `batch_size`, ordered by deployment id, so that memory
+/// use stays bounded regardless of how many deployments there are
+pub fn for_each_deployment_detail<F>(
+    conn: &PgConnection,
+    batch_size: usize,
+    mut f: F,
+) -> Result<(), StoreError>
+where
+    F: FnMut(DeploymentDetail) -> Result<(), StoreError>,
+{
+    use subgraph_deployment as d;
+
+    let batch_size = batch_size.max(1);
+    let mut last: Option<DeploymentId> = None;
+    loop {
+        let batch = match last {
+            None => d::table
+                .order_by(d::id)
+                .limit(batch_size as i64)
+                .load::<DeploymentDetail>(conn)?,
+            Some(last) => d::table
+                .filter(d::id.gt(last))
+                .order_by(d::id)
+                .limit(batch_size as i64)
+                .load::<DeploymentDetail>(conn)?,
+        };
+        let done = batch.len() < batch_size;
+        if let Some(detail) = batch.last() {
+            last = Some(detail.id);
+        }
+        for detail in batch {
+            f(detail)?;
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// Return the details for those deployments in `sites` that index
+/// `network`. Returns an empty list if none of them do
+pub fn deployment_details_by_network(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    network: &str,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    let ids: Vec<_> = sites
+        .iter()
+        .filter(|site| site.network == network)
+        .map(|site| site.id)
+        .collect();
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    d::table
+        .filter(d::id.eq_any(&ids))
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the details for the `limit` most recently created deployments,
+/// newest first. We do not record when a deployment was created, but
+/// deployment ids are assigned in increasing order, and we use them as a
+/// proxy for creation order
+pub fn recent_deployments(
+    conn: &PgConnection,
+    limit: i64,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .order_by(d::id.desc())
+        .limit(limit)
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the details of all deployments that changed after `watermark`,
+/// in ascending order of the sequence number of their latest change. Every
+/// insert into and update of `subgraph_deployment` assigns the row a new
+/// number from a sequence, kept in the `change_seq` column.
+///
+/// To poll for changes, start with a watermark of 0 and pass the
+/// `change_seq` of the last result as the watermark for the next call; a poll
+/// that returns nothing leaves the watermark unchanged. Each result only
+/// reflects the latest change to a deployment, intermediate states are not
+/// visible. Since the numbers are handed out when a row is written rather
+/// than when the transaction commits, a change from a transaction that was
+/// still in progress during a poll can get a number below the watermark;
+/// the deployment will be returned again with its next change
+pub fn deployment_details_changed_since(
+    conn: &PgConnection,
+    watermark: i64,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::change_seq.gt(watermark))
+        .order_by(d::change_seq)
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the details for all deployments that are synced if `synced` is
+/// `true`, and for all that are not synced otherwise
+pub fn deployment_details_by_synced(
+    conn: &PgConnection,
+    synced: bool,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::synced.eq(synced))
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the details for all deployments that are grafted onto `base`
+pub fn deployments_grafted_from(
+    conn: &PgConnection,
+    base: &DeploymentHash,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    // Deployments that are not grafted have a null `graft_base`, which
+    // never compares as equal to anything
+    d::table
+        .filter(d::graft_base.eq(base.as_str()))
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return `true` if any deployment has failed
+pub fn any_deployment_failed(conn: &PgConnection) -> Result<bool, StoreError> {
+    use subgraph_deployment as d;
+
+    diesel::select(dsl::exists(d::table.filter(d::failed.eq(true))))
+        .get_result::<bool>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return `true` if any deployment is unhealthy, i.e., has non-fatal
+/// errors. Failed deployments are not considered unhealthy
+pub fn any_deployment_unhealthy(conn: &PgConnection) -> Result<bool, StoreError> {
+    use subgraph_deployment as d;
+
+    diesel::select(dsl::exists(
+        d::table.filter(d::health.eq(HealthType::Unhealthy)),
+    ))
+    .get_result::<bool>(conn)
+    .map_err(StoreError::from)
+}
+
+/// Return the details for `sites` together with the node to which each
+/// deployment is assigned, or `None` if it is not assigned. An empty
+/// `sites` means 'all deployments'.
+///
+/// The assignments are read from the same database as the deployments.
+/// Shards only have a copy of the assignments in the primary that is
+/// refreshed periodically, and this function should therefore only be
+/// used when there is a single shard. Sharded setups should use
+/// `deployment_statuses_for_node` which reads assignments from the primary
+pub fn deployment_details_with_node(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<Vec<(DeploymentDetail, Option<String>)>, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_deployment_assignment as a;
+
+    let join = a::table.on(a::id.eq(d::id));
+
+    // Empty sites means 'all of them'
+    let details = if sites.is_empty() {
+        d::table
+            .left_outer_join(join)
+            .select((d::all_columns, a::node_id.nullable()))
+            .load::<(DeploymentDetail, Option<String>)>(conn)?
+    } else {
+        d::table
+            .left_outer_join(join)
+            .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
+            .select((d::all_columns, a::node_id.nullable()))
+            .load::<(DeploymentDetail, Option<String>)>(conn)?
+    };
+    Ok(details)
+}
+
+/// Return all failed deployments together with their fatal error. It is
+/// an error if a failed deployment does not have a fatal error
+pub fn failed_deployments_with_errors(
+    conn: &PgConnection,
+) -> Result<Vec<(DeploymentDetail, SubgraphError)>, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    // Use an outer join so that we notice failed deployments that are
+    // missing their error
+    d::table
+        .left_outer_join(e::table.on(e::id.nullable().eq(d::fatal_error)))
+        .filter(d::failed.eq(true))
+        .load::<(DeploymentDetail, Option<ErrorDetail>)>(conn)?
+        .into_iter()
+        .map(|(detail, fatal)| {
+            let fatal = fatal.ok_or_else(|| {
+                constraint_violation!(
+                    "deployment {} is marked as failed but has no fatal error",
+                    detail.deployment
+                )
+            })?;
+            Ok((detail, SubgraphError::try_from(fatal)?))
+        })
+        .collect()
+}
+
+/// Return the deployment hash, health, and the message of the fatal error
+/// of all failed deployments, ordered by deployment hash. Unlike
+/// `failed_deployments_with_errors`, a failed deployment without a fatal
+/// error is not an error and has a message of `None`
+pub fn failed_summaries(
+    conn: &PgConnection,
+) -> Result<Vec<(String, SubgraphHealth, Option<String>)>, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    let summaries = d::table
+        .left_outer_join(e::table.on(e::id.nullable().eq(d::fatal_error)))
+        .filter(d::failed.eq(true))
+        .select((d::deployment, d::health, e::message.nullable()))
+        .order_by(d::deployment)
+        .load::<(String, HealthType, Option<String>)>(conn)?;
+    Ok(summaries
+        .into_iter()
+        .map(|(deployment, health, message)| (deployment, health.into(), message))
+        .collect())
+}
+
+/// Return the details for the deployments `hashes`, keyed by their hash.
+/// Hashes for which there is no deployment are not in the result
+pub fn deployment_details_map(
+    conn: &PgConnection,
+    hashes: &[DeploymentHash],
+) -> Result<HashMap<DeploymentHash, DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    let hashes: Vec<_> = hashes.iter().map(|hash| hash.as_str()).collect();
+    d::table
+        .filter(d::deployment.eq_any(&hashes))
+        .load::<DeploymentDetail>(conn)?
+        .into_iter()
+        .map(|detail| {
+            let hash = DeploymentHash::new(detail.deployment.clone())
+                .map_err(|hash| constraint_violation!("invalid deployment hash `{}`", hash))?;
+            Ok((hash, detail))
+        })
+        .collect()
+}
+
+/// Escape `s` so that it matches literally in a `LIKE` pattern that uses
+/// the default escape character `\`
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Return the details for all deployments whose `deployment` column, i.e.
+/// the deployment hash, starts with `prefix`. The matching is case
+/// sensitive, and `%` and `_` in `prefix` only match themselves
+pub fn deployment_details_by_prefix(
+    conn: &PgConnection,
+    prefix: &str,
+) -> Result<Vec<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::deployment.like(format!("{}%", escape_like(prefix))))
+        .order_by(d::deployment)
+        .load::<DeploymentDetail>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the names of all networks that deployments index, sorted by
+/// name. Only deployments that are stored in the database for `conn` are
+/// considered
+pub fn active_networks(conn: &PgConnection) -> Result<Vec<String>, StoreError> {
+    use deployment_schemas as ds;
+    use subgraph_deployment as d;
+
+    ds::table
+        .inner_join(d::table.on(d::id.eq(ds::id)))
+        .select(ds::network)
+        .distinct()
+        .order_by(ds::network)
+        .load::<String>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the sum of the reorg counts of all deployments, or of only those
+/// that index `network`. As with `active_networks`, the network comes from
+/// joining `deployment_schemas` on the deployment id, and only deployments
+/// stored in the database for `conn` are considered. The sum is 0 if there
+/// are no such deployments
+pub fn total_reorg_count(conn: &PgConnection, network: Option<&str>) -> Result<i64, StoreError> {
+    use deployment_schemas as ds;
+    use subgraph_deployment as d;
+
+    let mut query = d::table
+        .inner_join(ds::table.on(ds::id.eq(d::id)))
+        .select(dsl::sum(d::reorg_count))
+        .into_boxed();
+    if let Some(network) = network {
+        query = query.filter(ds::network.eq(network));
+    }
+    let sum = query.get_result::<Option<i64>>(conn)?;
+    Ok(sum.unwrap_or(0))
+}
+
+/// Return the details for the deployment with hash `hash`, or `None` if
+/// there is no such deployment
+pub fn deployment_detail_by_hash(
+    conn: &PgConnection,
+    hash: &DeploymentHash,
+) -> Result<Option<DeploymentDetail>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::deployment.eq(hash.as_str()))
+        .first::<DeploymentDetail>(conn)
+        .optional()
+        .map_err(StoreError::from)
+}
+
+/// Return the health of the deployments `ids`. An empty `ids` means 'all
+/// deployments'
+pub fn deployment_healths(
+    conn: &PgConnection,
+    ids: &[DeploymentId],
+) -> Result<HashMap<DeploymentId, SubgraphHealth>, StoreError> {
+    use subgraph_deployment as d;
+
+    let healths = if ids.is_empty() {
+        d::table
+            .select((d::id, d::health))
+            .load::<(DeploymentId, HealthType)>(conn)?
+    } else {
+        d::table
+            .filter(d::id.eq_any(ids))
+            .select((d::id, d::health))
+            .load::<(DeploymentId, HealthType)>(conn)?
+    };
+    Ok(healths
+        .into_iter()
+        .map(|(id, health)| (id, health.into()))
+        .collect())
+}
+
+/// Aggregate numbers about all deployments, e.g., for exporting as gauges
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FleetMetrics {
+    pub deployments_total: i64,
+    pub deployments_healthy: i64,
+    pub deployments_unhealthy: i64,
+    pub deployments_failed: i64,
+    pub deployments_synced: i64,
+    /// The sum of the entity counts of all deployments. An `f64` represents
+    /// integers exactly only up to 2^53; larger sums are rounded
+    pub entity_count_sum: f64,
+}
+
+/// Compute `FleetMetrics` for all deployments in the database for `conn`
+/// with aggregate queries, without loading the details of deployments
+pub fn fleet_metrics(conn: &PgConnection) -> Result<FleetMetrics, StoreError> {
+    use subgraph_deployment as d;
+
+    let counts = d::table
+        .select((d::health, d::synced, dsl::sql::<BigInt>("count(*)")))
+        .group_by((d::health, d::synced))
+        .load::<(HealthType, bool, i64)>(conn)?;
+
+    let mut metrics = FleetMetrics::default();
+    for (health, synced, count) in counts {
+        metrics.deployments_total += count;
+        match health {
+            HealthType::Healthy => metrics.deployments_healthy += count,
+            HealthType::Unhealthy => metrics.deployments_unhealthy += count,
+            HealthType::Failed => metrics.deployments_failed += count,
+        }
+        if synced {
+            metrics.deployments_synced += count;
+        }
+    }
+
+    let sum = d::table
+        .select(dsl::sql::<diesel::sql_types::Numeric>(
+            "coalesce(sum(entity_count), 0)",
+        ))
+        .get_result::<BigDecimal>(conn)?;
+    // `ToPrimitive::to_f64` for `BigDecimal` goes through `i64` and fails
+    // for sums that do not fit into one; parsing never fails
+    metrics.entity_count_sum = sum.to_string().parse::<f64>().map_err(|e| {
+        constraint_violation!("the sum of entity counts {} is not a number: {}", sum, e)
+    })?;
+    Ok(metrics)
+}
+
+/// A compact summary of the status of a deployment, without any errors
+#[derive(Clone, Debug)]
+pub struct DeploymentOverview {
+    pub id: DeploymentId,
+    pub health: SubgraphHealth,
+    pub synced: bool,
+    pub latest_ethereum_block_number: Option<BigDecimal>,
+}
+
+/// Return an overview of the deployments `ids`. This is much cheaper than
+/// `deployment_statuses` since it only loads a few columns and does not
+/// look at errors at all. An empty `ids` means 'all deployments'
+pub fn deployment_overview(
+    conn: &PgConnection,
+    ids: &[DeploymentId],
+) -> Result<Vec<DeploymentOverview>, StoreError> {
+    use subgraph_deployment as d;
+
+    type Row = (DeploymentId, HealthType, bool, Option<BigDecimal>);
+
+    let columns = (d::id, d::health, d::synced, d::latest_ethereum_block_number);
+    let rows = if ids.is_empty() {
+        d::table.select(columns).load::<Row>(conn)?
+    } else {
+        d::table
+            .filter(d::id.eq_any(ids))
+            .select(columns)
+            .load::<Row>(conn)?
+    };
+    Ok(rows
+        .into_iter()
+        .map(
+            |(id, health, synced, latest_ethereum_block_number)| DeploymentOverview {
+                id,
+                health: health.into(),
+                synced,
+                latest_ethereum_block_number,
+            },
+        )
+        .collect())
+}
+
+/// Return the ids of all deployments that have at least one non-fatal
+/// error
+pub fn deployments_with_non_fatal_errors(
+    conn: &PgConnection,
+) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(dsl::sql::<Bool>("array_length(non_fatal_errors, 1) > 0"))
+        .select(d::id)
+        .load::<DeploymentId>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the ids of all deployments whose `max_reorg_depth` is less than
+/// `threshold`
+pub fn deployments_with_max_reorg_below(
+    conn: &PgConnection,
+    threshold: i32,
+) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::max_reorg_depth.lt(threshold))
+        .select(d::id)
+        .load::<DeploymentId>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the ids of all deployments that have never indexed a block,
+/// i.e., that do not have a latest block number. That is different from
+/// not being synced since an unsynced deployment might have indexed blocks
+pub fn deployments_never_indexed(conn: &PgConnection) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::latest_ethereum_block_number.is_null())
+        .select(d::id)
+        .load::<DeploymentId>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return the ids of all grafted deployments whose latest block is before
+/// their graft block, ordered by id. Grafting copies the data up to the
+/// graft block before the deployment starts indexing, so such a deployment
+/// is corrupt and needs to be repaired
+pub fn deployments_below_graft(conn: &PgConnection) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_deployment as d;
+
+    d::table
+        .filter(d::graft_base.is_not_null())
+        .filter(d::latest_ethereum_block_number.lt(d::graft_block_number))
+        .select(d::id)
+        .order_by(d::id)
+        .load::<DeploymentId>(conn)
+        .map_err(StoreError::from)
+}
+
+/// The details of a deployment together with its fatal and non-fatal
+/// errors
+type DetailAndErrors = (DeploymentDetail, Option<ErrorDetail>, Vec<ErrorDetail>);
+
+/// The order in which `deployment_statuses` returns statuses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatusOrder {
+    /// Whatever order the database produces; that order can change from
+    /// one call to the next
+    #[default]
+    Unordered,
+    /// By deployment hash
+    ByDeployment,
+    /// By latest block, highest first. Deployments that have not processed
+    /// any blocks come last
+    ByLatestBlockDesc,
+    /// By health in the order in which the `subgraphs.health` enum lists
+    /// them, i.e., failed, healthy, unhealthy, and by deployment hash for
+    /// the same health
+    ByHealth,
+}
+
+/// Load the details and errors for `sites`, sorted by `order`. An empty
+/// `sites` means 'all deployments'
+fn details_and_errors(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    order: StatusOrder,
+) -> Result<Vec<DetailAndErrors>, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    // First, we fetch all deployment information along with any fatal errors.
+    // Subsequently, we fetch non-fatal errors and we group them by deployment
+    // ID.
+
+    let details_with_fatal_error = {
+        let join = e::table.on(e::id.nullable().eq(d::fatal_error));
+
+        let mut query = d::table.left_outer_join(join).into_boxed();
+        // Empty deployments means 'all of them'
+        if !sites.is_empty() {
+            let ids: Vec<_> = sites.iter().map(|site| site.id).collect();
+            query = query.filter(d::id.eq_any(ids));
+        }
+        let query = match order {
+            StatusOrder::Unordered => query,
+            StatusOrder::ByDeployment => query.order_by(d::deployment.asc()),
+            StatusOrder::ByLatestBlockDesc => query.order_by((
+                dsl::sql::<diesel::sql_types::Numeric>(
+                    "subgraphs.subgraph_deployment.latest_ethereum_block_number desc nulls last",
+                ),
+                d::deployment.asc(),
+            )),
+            StatusOrder::ByHealth => query.order_by((d::health.asc(), d::deployment.asc())),
+        };
+        query.load::<(DeploymentDetail, Option<ErrorDetail>)>(conn)?
+    };
+
+    let mut non_fatal_errors = {
+        let join = e::table.on(e::id.eq(dsl::any(d::non_fatal_errors)));
+
+        if sites.is_empty() {
+            d::table
+                .inner_join(join)
+                .select((d::id, e::all_columns))
+                .load::<(DeploymentId, ErrorDetail)>(conn)?
+        } else {
+            d::table
+                .inner_join(join)
+                .filter(d::id.eq_any(sites.iter().map(|site| site.id)))
                 .select((d::id, e::all_columns))
                 .load::<(DeploymentId, ErrorDetail)>(conn)?
         }
@@ -312,13 +2056,305 @@ pub(crate) fn deployment_statuses(
         .into_group_map()
     };
 
-    details_with_fatal_error
+    Ok(details_with_fatal_error
         .into_iter()
         .map(|(detail, fatal)| {
             let non_fatal = non_fatal_errors.remove(&detail.id).unwrap_or(vec![]);
-            info_from_details(detail, fatal, non_fatal, sites)
+            (detail, fatal, non_fatal)
         })
-        .collect()
+        .collect())
+}
+
+/// Return the statuses of `sites`, sorted by `order`. An empty `sites`
+/// means 'all deployments'
+pub fn deployment_statuses(
+    logger: &Logger,
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    order: StatusOrder,
+) -> Result<Vec<status::Info>, StoreError> {
+    debug!(logger, "Loading deployment statuses";
+           "deployments" => deployment_filter(sites.iter().map(|site| site.deployment.as_str())));
+
+    let infos = details_and_errors(conn, sites, order).and_then(|details| {
+        details
+            .into_iter()
+            .map(|(detail, fatal, non_fatal)| {
+                info_from_details(detail, fatal, non_fatal, sites, &[])
+            })
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let infos = log_error(logger, "deployment_statuses", infos)?;
+
+    trace!(logger, "Loaded deployment statuses"; "count" => infos.len());
+    Ok(infos)
+}
+
+/// Like `deployment_statuses`, but report a `ChainInfo` for each of the
+/// networks in `networks` for a deployment, e.g., for subgraphs whose data
+/// sources index several networks. Deployments that have no entry in
+/// `networks` get the network of their site
+pub fn deployment_statuses_with_networks(
+    logger: &Logger,
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    networks: &HashMap<DeploymentId, Vec<String>>,
+    order: StatusOrder,
+) -> Result<Vec<status::Info>, StoreError> {
+    let infos = details_and_errors(conn, sites, order).and_then(|details| {
+        details
+            .into_iter()
+            .map(|(detail, fatal, non_fatal)| {
+                let chains = networks
+                    .get(&detail.id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                info_from_details(detail, fatal, non_fatal, sites, chains)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    });
+    log_error(logger, "deployment_statuses_with_networks", infos)
+}
+
+/// Check that all `infos` have a network
+fn check_networks(infos: &[status::Info]) -> Result<(), StoreError> {
+    match infos
+        .iter()
+        .find(|info| info.chains.iter().any(|chain| chain.network.is_empty()))
+    {
+        Some(info) => Err(constraint_violation!(
+            "the site for deployment {} has an empty network",
+            info.subgraph
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Like `deployment_statuses`, but fail if the site of any of the
+/// deployments has an empty network, rather than returning a status with
+/// an empty network
+pub fn deployment_statuses_strict(
+    logger: &Logger,
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    order: StatusOrder,
+) -> Result<Vec<status::Info>, StoreError> {
+    let infos = deployment_statuses(logger, conn, sites, order)?;
+    check_networks(&infos)?;
+    Ok(infos)
+}
+
+/// Like `deployment_statuses`, but retry up to `max_attempts` times if
+/// loading the statuses fails with a transient database error
+pub fn deployment_statuses_with_retry(
+    logger: &Logger,
+    pool: &ConnectionPool,
+    sites: &[Arc<Site>],
+    max_attempts: usize,
+) -> Result<Vec<status::Info>, StoreError> {
+    with_retry(
+        |conn| deployment_statuses(logger, conn, sites, StatusOrder::Unordered),
+        || pool.get(),
+        max_attempts,
+    )
+}
+
+/// Return `true` if `error` was caused by Postgres canceling a query
+/// because it ran longer than the `statement_timeout`
+fn is_statement_timeout(error: &StoreError) -> bool {
+    // Diesel does not give us the SQLSTATE of the error, and we have to
+    // go by the error message instead
+    match error {
+        StoreError::Unknown(e) => match e.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::DatabaseError(_, info)) => info
+                .message()
+                .contains("canceling statement due to statement timeout"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Like `deployment_statuses`, but cancel the query and return
+/// `StoreError::QueryTimeout` if it takes longer than `timeout`. The
+/// timeout is set with `set local` in a transaction and does not apply to
+/// other queries on `conn`, unless `conn` is already in a transaction in
+/// which case the timeout stays in effect until that transaction ends.
+/// Timeouts shorter than a millisecond, including zero, are rounded up to
+/// a millisecond, since a `statement_timeout` of 0 turns the timeout off
+pub fn deployment_statuses_with_timeout(
+    logger: &Logger,
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+    timeout: Duration,
+) -> Result<Vec<status::Info>, StoreError> {
+    conn.transaction(|| {
+        conn.batch_execute(&set_statement_timeout(timeout))?;
+        deployment_statuses(logger, conn, sites, StatusOrder::Unordered)
+    })
+    .map_err(|e| {
+        if is_statement_timeout(&e) {
+            StoreError::QueryTimeout
+        } else {
+            e
+        }
+    })
+}
+
+/// The SQL to set the statement timeout for the current transaction to
+/// `timeout`, but at least to one millisecond
+fn set_statement_timeout(timeout: Duration) -> String {
+    format!(
+        "set local statement_timeout = {}",
+        timeout.as_millis().max(1)
+    )
+}
+
+/// Like `deployment_statuses`, but a deployment whose status can not be
+/// constructed, e.g., because it has an invalid block pointer, does not
+/// make the whole call fail. Instead, the error for each such deployment
+/// is returned together with its deployment hash
+pub fn deployment_statuses_lenient(
+    conn: &PgConnection,
+    sites: &[Arc<Site>],
+) -> Result<(Vec<status::Info>, Vec<(String, StoreError)>), StoreError> {
+    let mut infos = Vec::new();
+    let mut errors = Vec::new();
+    for (detail, fatal, non_fatal) in details_and_errors(conn, sites, StatusOrder::Unordered)? {
+        let deployment = detail.deployment.clone();
+        match info_from_details(detail, fatal, non_fatal, sites, &[]) {
+            Ok(info) => infos.push(info),
+            Err(e) => errors.push((deployment, e)),
+        }
+    }
+    Ok((infos, errors))
+}
+
+/// Return the statuses of `sites` which can be spread across several
+/// shards. Each site is looked up with the connection for its shard in
+/// `conns`, and it is an error if there is no connection for a site's
+/// shard. Unlike for `deployment_statuses`, an empty `sites` returns no
+/// statuses
+pub fn deployment_statuses_sharded(
+    logger: &Logger,
+    conns: &HashMap<Shard, PgConnection>,
+    sites: &[Arc<Site>],
+) -> Result<Vec<status::Info>, StoreError> {
+    let by_shard = sites
+        .iter()
+        .map(|site| (site.shard.clone(), site.clone()))
+        .into_group_map();
+
+    let mut infos = Vec::with_capacity(sites.len());
+    for (shard, sites) in by_shard {
+        let conn = conns.get(&shard).ok_or_else(|| {
+            constraint_violation!(
+                "no connection for shard `{}` which holds deployments {}",
+                shard.as_str(),
+                sites.iter().map(|site| site.deployment.as_str()).join(", ")
+            )
+        })?;
+        infos.extend(deployment_statuses(
+            logger,
+            conn,
+            &sites,
+            StatusOrder::Unordered,
+        )?);
+    }
+    Ok(infos)
+}
+
+/// Return the statuses of the deployments in `sites` that are assigned to
+/// `node_id`, with `node` filled in. The assignments are read through
+/// `primary_conn`, which must be a connection to the primary shard; `conn`
+/// must be a connection to the shard that holds the deployments. Only
+/// deployments that are in `sites` are considered
+pub fn deployment_statuses_for_node(
+    logger: &Logger,
+    conn: &PgConnection,
+    primary_conn: &PgConnection,
+    node_id: &str,
+    sites: &[Arc<Site>],
+) -> Result<Vec<status::Info>, StoreError> {
+    use crate::primary::subgraph_deployment_assignment as a;
+
+    let assigned: HashSet<DeploymentId> = a::table
+        .filter(a::node_id.eq(node_id))
+        .select(a::id)
+        .load::<DeploymentId>(primary_conn)?
+        .into_iter()
+        .collect();
+
+    let sites: Vec<_> = sites
+        .iter()
+        .filter(|site| assigned.contains(&site.id))
+        .cloned()
+        .collect();
+    // `deployment_statuses` treats an empty list of sites as 'all of
+    // them', but we want none
+    if sites.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut infos = deployment_statuses(logger, conn, &sites, StatusOrder::Unordered)?;
+    for info in &mut infos {
+        info.node = Some(node_id.to_string());
+    }
+    Ok(infos)
+}
+
+/// Set the health of the deployments `ids` to `health` and return the
+/// number of deployments that were changed. Unless `force` is `true`, it
+/// is an error to try and mark a failed deployment as anything but failed,
+/// since that would also clear its `failed` flag. `health` is the health
+/// type from `graph` since the one used for the database is not visible
+/// outside of this crate
+pub fn mark_deployments_health(
+    conn: &PgConnection,
+    ids: &[DeploymentId],
+    health: SubgraphHealth,
+    force: bool,
+) -> Result<usize, StoreError> {
+    use diesel::BoolExpressionMethods;
+    use subgraph_deployment as d;
+
+    let changes = (
+        d::failed.eq(health.is_failed()),
+        d::health.eq(HealthType::from(health)),
+    );
+
+    if force || health.is_failed() {
+        return diesel::update(d::table.filter(d::id.eq_any(ids)))
+            .set(changes)
+            .execute(conn)
+            .map_err(StoreError::from);
+    }
+
+    let failed = d::table
+        .filter(d::id.eq_any(ids))
+        .filter(d::health.eq(HealthType::Failed).or(d::failed.eq(true)))
+        .select(d::deployment)
+        .order_by(d::deployment)
+        .load::<String>(conn)?;
+    if !failed.is_empty() {
+        return Err(constraint_violation!(
+            "refusing to mark failed deployments as {} without `force`: {}",
+            health.as_str(),
+            failed.join(", ")
+        ));
+    }
+
+    // Check the health again when updating so that a deployment that
+    // failed since we looked does not lose its failed state
+    diesel::update(
+        d::table
+            .filter(d::id.eq_any(ids))
+            .filter(d::health.ne(HealthType::Failed))
+            .filter(d::failed.eq(false)),
+    )
+    .set(changes)
+    .execute(conn)
+    .map_err(StoreError::from)
 }
 
 #[derive(Queryable, QueryableByName, Identifiable, Associations)]
@@ -338,15 +2374,48 @@ struct StoredSubgraphManifest {
     use_bytea_prefix: bool,
 }
 
-impl From<StoredSubgraphManifest> for SubgraphManifestEntity {
-    fn from(value: StoredSubgraphManifest) -> Self {
-        SubgraphManifestEntity {
-            spec_version: value.spec_version,
+impl StoredSubgraphManifest {
+    /// Return `true` if the spec version of the manifest is at least
+    /// `major.minor.patch`. Versions are compared according to semver, so
+    /// that a pre-release like `0.0.5-rc1` comes before `0.0.5`
+    fn spec_version_at_least(
+        &self,
+        major: u32,
+        minor: u32,
+        patch: u32,
+    ) -> Result<bool, StoreError> {
+        let version = graph::semver::Version::parse(&self.spec_version).map_err(|e| {
+            constraint_violation!(
+                "invalid spec version `{}` for deployment with id {}: {}",
+                self.spec_version,
+                self.id,
+                e
+            )
+        })?;
+        let min = graph::semver::Version::new(major.into(), minor.into(), patch.into());
+        Ok(version >= min)
+    }
+}
+
+impl TryFrom<StoredSubgraphManifest> for SubgraphManifestEntity {
+    type Error = StoreError;
+
+    fn try_from(value: StoredSubgraphManifest) -> Result<Self, Self::Error> {
+        let spec_version = value.spec_version.parse().map_err(|e| {
+            constraint_violation!(
+                "invalid spec version `{}` for deployment with id {}: {}",
+                value.spec_version,
+                value.id,
+                e
+            )
+        })?;
+        Ok(SubgraphManifestEntity {
+            spec_version,
             description: value.description,
             repository: value.repository,
-            features: value.features,
+            features: value.features.into(),
             schema: value.schema,
-        }
+        })
     }
 }
 
@@ -356,7 +2425,7 @@ impl TryFrom<StoredDeploymentEntity> for SubgraphDeploymentEntity {
     type Error = StoreError;
 
     fn try_from(ent: StoredDeploymentEntity) -> Result<Self, Self::Error> {
-        let (detail, manifest) = (ent.0, ent.1.into());
+        let (detail, manifest) = (ent.0, SubgraphManifestEntity::try_from(ent.1)?);
 
         let earliest_block = block(
             &detail.deployment,
@@ -366,72 +2435,306 @@ impl TryFrom<StoredDeploymentEntity> for SubgraphDeploymentEntity {
         )?
         .map(|block| block.to_ptr());
 
-        let latest_block = block(
-            &detail.deployment,
-            "latest_block",
-            detail.latest_ethereum_block_hash,
-            detail.latest_ethereum_block_number,
-        )?
-        .map(|block| block.to_ptr());
+        let latest_block = block(
+            &detail.deployment,
+            "latest_block",
+            detail.latest_ethereum_block_hash,
+            detail.latest_ethereum_block_number,
+        )?
+        .map(|block| block.to_ptr());
+
+        let graft_block = block(
+            &detail.deployment,
+            "graft_block",
+            detail.graft_block_hash,
+            detail.graft_block_number,
+        )?
+        .map(|block| block.to_ptr());
+
+        let graft_base = detail
+            .graft_base
+            .map(DeploymentHash::new)
+            .transpose()
+            .map_err(|b| constraint_violation!("invalid graft base `{}`", b))?;
+
+        let debug_fork = detail
+            .debug_fork
+            .map(DeploymentHash::new)
+            .transpose()
+            .map_err(|b| constraint_violation!("invalid debug fork `{}`", b))?;
+
+        Ok(SubgraphDeploymentEntity {
+            manifest,
+            failed: detail.failed,
+            health: detail.health.into(),
+            synced: detail.synced,
+            fatal_error: None,
+            non_fatal_errors: vec![],
+            earliest_block,
+            latest_block,
+            graft_base,
+            graft_block,
+            debug_fork,
+            reorg_count: detail.reorg_count,
+            current_reorg_depth: detail.current_reorg_depth,
+            max_reorg_depth: detail.max_reorg_depth,
+        })
+    }
+}
+
+/// Return how many blocks the graft block of `entity` is after its
+/// earliest block, or `None` if the deployment is not grafted or has no
+/// earliest block. Block numbers are `i32`, so the difference always fits
+/// into an `i64`
+pub fn graft_gap(entity: &SubgraphDeploymentEntity) -> Option<i64> {
+    let graft = entity.graft_block.as_ref()?;
+    let earliest = entity.earliest_block.as_ref()?;
+    Some(graft.number as i64 - earliest.number as i64)
+}
+
+pub fn deployment_entity(
+    logger: &Logger,
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<SubgraphDeploymentEntity, StoreError> {
+    deployment_entity_opts(logger, conn, site, true)
+}
+
+/// Run `f` in a read-only transaction with isolation level `repeatable
+/// read` so that all queries in `f` see the same snapshot of the database,
+/// even if other transactions change it while `f` runs. Since the
+/// isolation level can only be set when a transaction starts, it is an
+/// error if `conn` is already in a transaction; Diesel would otherwise run
+/// `f` in a savepoint of that transaction with its isolation level
+pub fn in_snapshot<T, F>(conn: &PgConnection, f: F) -> Result<T, StoreError>
+where
+    F: FnOnce() -> Result<T, StoreError>,
+{
+    use diesel::connection::TransactionManager;
+
+    let depth =
+        <_ as TransactionManager<PgConnection>>::get_transaction_depth(conn.transaction_manager());
+    if depth > 0 {
+        return Err(constraint_violation!(
+            "can not read from a snapshot since the connection is already in a transaction"
+        ));
+    }
+    conn.build_transaction()
+        .repeatable_read()
+        .read_only()
+        .run(f)
+}
+
+/// Like `deployment_entity`, but load the manifest and the details of the
+/// deployment from the same snapshot of the database with `in_snapshot`.
+/// Without that, a concurrent change, e.g., a rewind that moves the block
+/// pointers, can happen between the two queries, and the entity would mix
+/// data from before and after the change
+pub fn deployment_entity_consistent(
+    logger: &Logger,
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<SubgraphDeploymentEntity, StoreError> {
+    in_snapshot(conn, || deployment_entity(logger, conn, site))
+}
+
+/// Like `deployment_entity`, but only load the schema of the deployment if
+/// `include_schema` is `true`. Otherwise, the `schema` of the manifest in
+/// the result is the empty string
+pub fn deployment_entity_opts(
+    logger: &Logger,
+    conn: &PgConnection,
+    site: &Site,
+    include_schema: bool,
+) -> Result<SubgraphDeploymentEntity, StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_manifest as m;
+
+    debug!(logger, "Loading deployment entity";
+           "deployment" => site.deployment.as_str(),
+           "include_schema" => include_schema);
+
+    let manifest = if include_schema {
+        m::table.find(site.id).first::<StoredSubgraphManifest>(conn)
+    } else {
+        manifest_without_schema(conn, site)
+    };
+
+    let entity = manifest.map_err(StoreError::from).and_then(|manifest| {
+        let detail = d::table
+            .find(site.id)
+            .first::<crate::detail::DeploymentDetail>(conn)?;
+
+        SubgraphDeploymentEntity::try_from(StoredDeploymentEntity(detail, manifest))
+    });
+    log_error(logger, "deployment_entity", entity)
+}
+
+/// A fingerprint of the parts of `detail` that change while the deployment
+/// is being indexed. The fingerprint changes whenever any of them change
+fn detail_etag(detail: &DeploymentDetail) -> String {
+    let fields = format!(
+        "{}|{}|{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}|{}|{}|{}",
+        SubgraphHealth::from(detail.health).as_str(),
+        detail.failed,
+        detail.synced,
+        detail.fatal_error,
+        detail.non_fatal_errors,
+        detail.earliest_ethereum_block_hash,
+        detail.earliest_ethereum_block_number,
+        detail.latest_ethereum_block_hash,
+        detail.latest_ethereum_block_number,
+        detail.entity_count,
+        detail.reorg_count,
+        detail.current_reorg_depth,
+        detail.max_reorg_depth
+    );
+    blake3::hash(fields.as_bytes()).to_hex().to_string()
+}
+
+/// Return an etag for the deployment `site` together with its entity.
+/// The etag can be passed to `deployment_entity_if_changed` to avoid
+/// loading the entity again if nothing changed. The entity is always
+/// `Some`
+pub fn deployment_entity_etag(
+    logger: &Logger,
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<(String, Option<SubgraphDeploymentEntity>), StoreError> {
+    // An etag is never empty, and the entity is therefore always loaded
+    deployment_entity_if_changed(logger, conn, site, "")
+}
+
+/// Return the current etag for the deployment `site`, and, if it differs
+/// from `prev_etag`, the entity for the deployment. Since the etag is
+/// computed before the entity is loaded, the entity might already contain
+/// changes that the etag does not reflect yet; that only means that the
+/// next call will load the entity again
+pub fn deployment_entity_if_changed(
+    logger: &Logger,
+    conn: &PgConnection,
+    site: &Site,
+    prev_etag: &str,
+) -> Result<(String, Option<SubgraphDeploymentEntity>), StoreError> {
+    use subgraph_deployment as d;
+
+    let detail = d::table.find(site.id).first::<DeploymentDetail>(conn)?;
+    let etag = detail_etag(&detail);
+    if etag == prev_etag {
+        return Ok((etag, None));
+    }
+    let entity = deployment_entity(logger, conn, site)?;
+    Ok((etag, Some(entity)))
+}
+
+/// Load the manifest of `site` with an empty string instead of the schema,
+/// which can be large
+fn manifest_without_schema(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<StoredSubgraphManifest, diesel::result::Error> {
+    use subgraph_manifest as m;
+
+    m::table
+        .find(site.id)
+        .select((
+            m::id,
+            m::spec_version,
+            m::description,
+            m::repository,
+            m::features,
+            dsl::sql::<Text>("''"),
+            m::graph_node_version_id,
+            m::use_bytea_prefix,
+        ))
+        .first::<StoredSubgraphManifest>(conn)
+}
 
-        let graft_block = block(
-            &detail.deployment,
-            "graft_block",
-            detail.graft_block_hash,
-            detail.graft_block_number,
-        )?
-        .map(|block| block.to_ptr());
+/// Return `true` if the spec version of the manifest of `site` is at
+/// least `major.minor.patch`
+pub fn spec_version_at_least(
+    conn: &PgConnection,
+    site: &Site,
+    major: u32,
+    minor: u32,
+    patch: u32,
+) -> Result<bool, StoreError> {
+    manifest_without_schema(conn, site)?.spec_version_at_least(major, minor, patch)
+}
 
-        let graft_base = detail
-            .graft_base
-            .map(DeploymentHash::new)
-            .transpose()
-            .map_err(|b| constraint_violation!("invalid graft base `{}`", b))?;
+/// Return the GraphQL schema of the deployment `site`. This is much
+/// cheaper than `deployment_entity` when only the schema is needed
+pub fn deployment_schema(conn: &PgConnection, site: &Site) -> Result<String, StoreError> {
+    use subgraph_manifest as m;
 
-        let debug_fork = detail
-            .debug_fork
-            .map(DeploymentHash::new)
-            .transpose()
-            .map_err(|b| constraint_violation!("invalid debug fork `{}`", b))?;
+    m::table
+        .find(site.id)
+        .select(m::schema)
+        .first::<String>(conn)
+        .map_err(StoreError::from)
+}
 
-        Ok(SubgraphDeploymentEntity {
-            manifest,
-            failed: detail.failed,
-            health: detail.health.into(),
-            synced: detail.synced,
-            fatal_error: None,
-            non_fatal_errors: vec![],
-            earliest_block,
-            latest_block,
-            graft_base,
-            graft_block,
-            debug_fork,
-            reorg_count: detail.reorg_count,
-            current_reorg_depth: detail.current_reorg_depth,
-            max_reorg_depth: detail.max_reorg_depth,
-        })
-    }
+/// A stable hash of the GraphQL `schema` of a deployment, encoded as hex.
+/// Deployments with identical schemas have the same hash
+pub fn schema_hash(schema: &str) -> String {
+    blake3::hash(schema.as_bytes()).to_hex().to_string()
 }
 
-pub fn deployment_entity(
+/// Group all deployments by the `schema_hash` of their schema. We do not
+/// store the hash of schemas, and this function therefore loads the
+/// schemas of all deployments into memory at once, which can amount to a
+/// lot of memory for installations with many deployments
+pub fn group_deployments_by_schema(
+    conn: &PgConnection,
+) -> Result<HashMap<String, Vec<DeploymentId>>, StoreError> {
+    use subgraph_manifest as m;
+
+    let schemas = m::table
+        .select((m::id, m::schema))
+        .load::<(DeploymentId, String)>(conn)?;
+    Ok(schemas
+        .into_iter()
+        .map(|(id, schema)| (schema_hash(&schema), id))
+        .into_group_map())
+}
+
+/// Return both the status and the deployment entity for `site`. This is
+/// cheaper than calling `deployment_statuses` and `deployment_entity`
+/// separately since the details of the deployment are only loaded once
+pub fn deployment_full(
     conn: &PgConnection,
     site: &Site,
-) -> Result<SubgraphDeploymentEntity, StoreError> {
+    sites: &[Arc<Site>],
+) -> Result<(status::Info, SubgraphDeploymentEntity), StoreError> {
     use subgraph_deployment as d;
+    use subgraph_error as e;
     use subgraph_manifest as m;
 
     let manifest = m::table
         .find(site.id)
         .first::<StoredSubgraphManifest>(conn)?;
 
-    let detail = d::table
-        .find(site.id)
-        .first::<crate::detail::DeploymentDetail>(conn)?;
+    let (detail, fatal) = d::table
+        .left_outer_join(e::table.on(e::id.nullable().eq(d::fatal_error)))
+        .filter(d::id.eq(site.id))
+        .first::<(DeploymentDetail, Option<ErrorDetail>)>(conn)?;
 
-    SubgraphDeploymentEntity::try_from(StoredDeploymentEntity(detail, manifest))
+    let non_fatal = d::table
+        .inner_join(e::table.on(e::id.eq(dsl::any(d::non_fatal_errors))))
+        .filter(d::id.eq(site.id))
+        .select(e::all_columns)
+        .load::<ErrorDetail>(conn)?;
+
+    let entity =
+        SubgraphDeploymentEntity::try_from(StoredDeploymentEntity(detail.clone(), manifest))?;
+    let info = info_from_details(detail, fatal, non_fatal, sites, &[])?;
+    Ok((info, entity))
 }
 
-#[derive(Queryable, Identifiable, Insertable)]
+/// A row of `graph_node_versions`. When serialized, e.g., for diagnostics
+/// bundles, the fields keep the names of the table's columns; external
+/// tools rely on them, so they must not be renamed
+#[derive(Queryable, Identifiable, Insertable, Serialize)]
 #[table_name = "graph_node_versions"]
 pub struct GraphNodeVersion {
     pub id: i32,
@@ -443,20 +2746,61 @@ pub struct GraphNodeVersion {
     pub patch: i32,
 }
 
+/// How the version of graph-node changed between two builds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionChange {
+    Same,
+    Upgrade,
+    Downgrade,
+    /// The crate versions are the same, but the builds come from
+    /// different commits
+    SameVersionDifferentCommit,
+}
+
+/// Version information about the running graph-node binary
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphNodeVersionInfo {
+    pub git_commit_hash: String,
+    pub git_repository_dirty: bool,
+    pub crate_version: String,
+    pub major: i32,
+    pub minor: i32,
+    pub patch: i32,
+}
+
 impl GraphNodeVersion {
-    pub(crate) fn create_or_get(conn: &PgConnection) -> anyhow::Result<i32> {
-        let git_commit_hash = version_commit_hash!();
-        let git_repository_dirty = !&TESTAMENT.modifications.is_empty();
-        let crate_version = CARGO_PKG_VERSION;
+    /// Return the version information for the running binary. This does
+    /// not access the database
+    pub fn current() -> anyhow::Result<GraphNodeVersionInfo> {
         let major: i32 = CARGO_PKG_VERSION_MAJOR
             .parse()
-            .expect("failed to parse cargo major package version");
+            .context("failed to parse cargo major package version")?;
         let minor: i32 = CARGO_PKG_VERSION_MINOR
             .parse()
-            .expect("failed to parse cargo major package version");
+            .context("failed to parse cargo minor package version")?;
         let patch: i32 = CARGO_PKG_VERSION_PATCH
             .parse()
-            .expect("failed to parse cargo major package version");
+            .context("failed to parse cargo patch package version")?;
+
+        Ok(GraphNodeVersionInfo {
+            git_commit_hash: version_commit_hash!().to_string(),
+            git_repository_dirty: !TESTAMENT.modifications.is_empty(),
+            crate_version: CARGO_PKG_VERSION.to_string(),
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    pub(crate) fn create_or_get(conn: &PgConnection) -> anyhow::Result<i32> {
+        let GraphNodeVersionInfo {
+            git_commit_hash,
+            git_repository_dirty,
+            crate_version,
+            major,
+            minor,
+            patch,
+        } = Self::current()?;
 
         let graph_node_version_id = {
             use graph_node_versions::dsl as g;
@@ -487,4 +2831,867 @@ impl GraphNodeVersion {
         };
         Ok(graph_node_version_id)
     }
+
+    /// Look up a previously recorded version by its git commit hash.
+    /// Unlike `create_or_get`, this never writes to the database. If
+    /// several versions were recorded for the same commit, e.g., from a
+    /// clean and a dirty checkout, the one that was recorded first is
+    /// returned
+    pub fn find_by_commit(
+        conn: &PgConnection,
+        commit_hash: &str,
+    ) -> Result<Option<GraphNodeVersion>, StoreError> {
+        use graph_node_versions as v;
+
+        v::table
+            .filter(v::git_commit_hash.eq(commit_hash))
+            .order_by(v::id)
+            .first::<GraphNodeVersion>(conn)
+            .optional()
+            .map_err(StoreError::from)
+    }
+
+    /// The pre-release part of the crate version, e.g., `rc1` for
+    /// `1.0.0-rc1`
+    fn pre_release(&self) -> Option<&str> {
+        self.crate_version
+            .split_once('+')
+            .map(|(version, _)| version)
+            .unwrap_or(self.crate_version.as_str())
+            .split_once('-')
+            .map(|(_, pre)| pre)
+    }
+
+    /// Describe how the version changed when going from `self` to `other`.
+    /// Versions are compared following the precedence rules of semver, and
+    /// builds with the same version are distinguished by their commit
+    pub fn describe_change(&self, other: &GraphNodeVersion) -> VersionChange {
+        let ours = (self.major, self.minor, self.patch);
+        let theirs = (other.major, other.minor, other.patch);
+        let order = ours
+            .cmp(&theirs)
+            .then_with(|| cmp_pre_release(self.pre_release(), other.pre_release()));
+        match order {
+            Ordering::Less => VersionChange::Upgrade,
+            Ordering::Greater => VersionChange::Downgrade,
+            Ordering::Equal if self.git_commit_hash != other.git_commit_hash => {
+                VersionChange::SameVersionDifferentCommit
+            }
+            Ordering::Equal => VersionChange::Same,
+        }
+    }
+}
+
+/// Compare two pre-release tags according to semver: a version without a
+/// pre-release tag ranks higher than one with a tag, and tags are compared
+/// identifier by identifier, numerically where both identifiers are numeric
+fn cmp_pre_release(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            for (a, b) in a.split('.').zip(b.split('.')) {
+                let order = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => a.cmp(b),
+                };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            a.split('.').count().cmp(&b.split('.').count())
+        }
+    }
+}
+
+/// Return the version of graph-node that created the deployment `site`.
+/// Returns `None` if the manifest does not reference a version, which is
+/// the case for deployments that were created before we started tracking
+/// versions.
+///
+/// Note that we only record the git commit and crate version of the
+/// build; the rust toolchain that produced the binary is not stored
+pub fn graph_node_version_for_deployment(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<GraphNodeVersion>, StoreError> {
+    use graph_node_versions as v;
+    use subgraph_manifest as m;
+
+    m::table
+        .inner_join(v::table.on(m::graph_node_version_id.eq(v::id.nullable())))
+        .filter(m::id.eq(site.id))
+        .select(v::all_columns)
+        .first::<GraphNodeVersion>(conn)
+        .optional()
+        .map_err(StoreError::from)
+}
+
+/// Return whether the deployment `site` was created by a build of
+/// graph-node from a git checkout with uncommitted changes, or `None` if
+/// we do not know which version of graph-node created the deployment.
+/// Results from such deployments might not be reproducible
+pub fn deployment_built_from_dirty_tree(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Option<bool>, StoreError> {
+    use graph_node_versions as v;
+    use subgraph_manifest as m;
+
+    m::table
+        .inner_join(v::table.on(m::graph_node_version_id.eq(v::id.nullable())))
+        .filter(m::id.eq(site.id))
+        .select(v::git_repository_dirty)
+        .first::<bool>(conn)
+        .optional()
+        .map_err(StoreError::from)
+}
+
+/// Return the ids of all deployments for which we do not know which version
+/// of graph-node created them since they were created before we tracked
+/// that. A foreign key makes sure that a `graph_node_version_id` that is
+/// not null always refers to an existing version
+pub fn deployments_without_version(conn: &PgConnection) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_manifest as m;
+
+    m::table
+        .filter(m::graph_node_version_id.is_null())
+        .select(m::id)
+        .order_by(m::id)
+        .load::<DeploymentId>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return all versions of graph-node that created at least one of the
+/// deployments in the database for `conn`, ordered by their id, i.e., in
+/// the order in which they were first used
+pub fn distinct_graph_node_versions(
+    conn: &PgConnection,
+) -> Result<Vec<GraphNodeVersion>, StoreError> {
+    use graph_node_versions as v;
+    use subgraph_manifest as m;
+
+    v::table
+        .filter(dsl::exists(
+            m::table.filter(m::graph_node_version_id.eq(v::id.nullable())),
+        ))
+        .order_by(v::id)
+        .load::<GraphNodeVersion>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Return all versions of graph-node that were ever recorded, ordered by
+/// their id, including those that no deployment references anymore
+pub fn all_graph_node_versions(conn: &PgConnection) -> Result<Vec<GraphNodeVersion>, StoreError> {
+    use graph_node_versions as v;
+
+    v::table
+        .order_by(v::id)
+        .load::<GraphNodeVersion>(conn)
+        .map_err(StoreError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// A healthy, synced deployment that has not processed any blocks
+    fn test_detail() -> DeploymentDetail {
+        DeploymentDetailBuilder::new(DeploymentId::from(GraphDeploymentId::new(1)), "QmTest")
+            .earliest_block(None)
+            .latest_block(None)
+            .build()
+    }
+
+    fn version(crate_version: &str, commit: &str) -> GraphNodeVersion {
+        let semver = crate_version.split(&['-', '+'][..]).next().unwrap();
+        let parts: Vec<i32> = semver.split('.').map(|p| p.parse().unwrap()).collect();
+        GraphNodeVersion {
+            id: 1,
+            git_commit_hash: commit.to_string(),
+            git_repository_dirty: false,
+            crate_version: crate_version.to_string(),
+            major: parts[0],
+            minor: parts[1],
+            patch: parts[2],
+        }
+    }
+
+    #[test]
+    fn block_checks_hash_length() {
+        const ID: &str = "QmTest";
+        const NAME: &str = "latest_ethereum_block";
+
+        let ptr = block(ID, NAME, Some(vec![7u8; 32]), Some(BigDecimal::from(12)))
+            .unwrap()
+            .unwrap()
+            .to_ptr();
+        assert_eq!(12, ptr.number);
+        assert_eq!(H256::repeat_byte(7), ptr.hash_as_h256());
+
+        match block(ID, NAME, Some(vec![7u8; 20]), Some(BigDecimal::from(12))) {
+            Err(StoreError::ConstraintViolation(msg)) => {
+                assert!(msg.contains(ID));
+                assert!(msg.contains(NAME));
+            }
+            res => panic!("expected a constraint violation but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn block_columns_round_trip() {
+        const ID: &str = "QmTest";
+        const NAME: &str = "latest_ethereum_block";
+
+        let ptr = BlockPtr::from((H256::repeat_byte(7), 12));
+        let (hash, number) = block_columns(Some(&ptr));
+        assert_eq!(Some(vec![7u8; 32]), hash);
+        assert_eq!(Some(BigDecimal::from(12)), number);
+        let round_trip = block(ID, NAME, hash, number).unwrap().unwrap().to_ptr();
+        assert_eq!(ptr, round_trip);
+
+        let (hash, number) = block_columns(None);
+        assert_eq!((None, None), (hash.clone(), number.clone()));
+        assert!(block(ID, NAME, hash, number).unwrap().is_none());
+    }
+
+    #[test]
+    fn reorg_within_bounds() {
+        let mut detail = test_detail();
+        detail.max_reorg_depth = 5;
+
+        detail.current_reorg_depth = 4;
+        assert!(check_reorg_within_bounds(&detail).is_ok());
+
+        detail.current_reorg_depth = 5;
+        assert!(check_reorg_within_bounds(&detail).is_ok());
+
+        detail.current_reorg_depth = 6;
+        match check_reorg_within_bounds(&detail) {
+            Err(StoreError::ReorgDepthExceeded {
+                deployment,
+                current,
+                max,
+            }) => {
+                assert_eq!("QmTest", deployment);
+                assert_eq!(6, current);
+                assert_eq!(5, max);
+            }
+            res => panic!("expected ReorgDepthExceeded but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn stalled() {
+        let mut before = test_detail();
+        before.synced = false;
+        let mut after = before.clone();
+        assert!(!is_stalled(&before, &after));
+
+        before.latest_ethereum_block_number = Some(BigDecimal::from(10));
+        after.latest_ethereum_block_number = Some(BigDecimal::from(10));
+        assert!(is_stalled(&before, &after));
+
+        after.synced = true;
+        assert!(!is_stalled(&before, &after));
+
+        after.synced = false;
+        after.latest_ethereum_block_number = Some(BigDecimal::from(11));
+        assert!(!is_stalled(&before, &after));
+    }
+
+    #[test]
+    fn entity_count_cap() {
+        let mut detail = test_detail();
+        detail.entity_count = BigDecimal::from(10);
+        assert!(!entity_count_exceeds(&detail, 10).unwrap());
+        assert!(entity_count_exceeds(&detail, 9).unwrap());
+
+        detail.entity_count = BigDecimal::from(u64::MAX) + BigDecimal::from(1);
+        assert!(entity_count_exceeds(&detail, u64::MAX).unwrap());
+
+        detail.entity_count = BigDecimal::from(-1);
+        assert!(entity_count_exceeds(&detail, 10).is_err());
+    }
+
+    #[test]
+    fn retryable_errors() {
+        fn db_error_msg(kind: DatabaseErrorKind, msg: &str) -> StoreError {
+            StoreError::from(diesel::result::Error::DatabaseError(
+                kind,
+                Box::new(msg.to_string()),
+            ))
+        }
+        fn db_error(kind: DatabaseErrorKind) -> StoreError {
+            db_error_msg(kind, "boom")
+        }
+
+        assert!(is_transient(&StoreError::DatabaseUnavailable));
+        assert!(is_transient(&db_error(
+            DatabaseErrorKind::UnableToSendCommand
+        )));
+        assert!(is_transient(&db_error_msg(
+            DatabaseErrorKind::__Unknown,
+            "server closed the connection unexpectedly\n\tThis probably means \
+             the server terminated abnormally"
+        )));
+        assert!(!is_transient(&db_error(DatabaseErrorKind::__Unknown)));
+        assert!(!is_transient(&db_error(DatabaseErrorKind::UniqueViolation)));
+        assert!(!is_transient(&db_error(
+            DatabaseErrorKind::ForeignKeyViolation
+        )));
+        assert!(!is_transient(&StoreError::from(
+            diesel::result::Error::NotFound
+        )));
+        assert!(!is_transient(&constraint_violation!("bad data")));
+
+        // Every attempt asks for a new connection
+        let attempts = std::cell::Cell::new(0);
+        let res = with_retry(
+            |_| Ok(()),
+            || -> Result<Box<PgConnection>, StoreError> {
+                attempts.set(attempts.get() + 1);
+                Err(StoreError::DatabaseUnavailable)
+            },
+            3,
+        );
+        assert!(matches!(res, Err(StoreError::DatabaseUnavailable)));
+        assert_eq!(3, attempts.get());
+
+        attempts.set(0);
+        let res = with_retry(
+            |_| Ok(()),
+            || -> Result<Box<PgConnection>, StoreError> {
+                attempts.set(attempts.get() + 1);
+                Err(constraint_violation!("bad data"))
+            },
+            3,
+        );
+        assert!(matches!(res, Err(StoreError::ConstraintViolation(_))));
+        assert_eq!(1, attempts.get());
+    }
+
+    #[test]
+    fn block_range_bounds() {
+        assert_eq!(
+            BlockRange {
+                start: Some(3),
+                end: None
+            },
+            BlockRange::from((Bound::Included(3), Bound::Unbounded))
+        );
+        assert_eq!(
+            BlockRange {
+                start: Some(4),
+                end: Some(8)
+            },
+            BlockRange::from((Bound::Excluded(3), Bound::Included(7)))
+        );
+        assert_eq!(
+            BlockRange {
+                start: None,
+                end: Some(7)
+            },
+            BlockRange::from((Bound::Unbounded, Bound::Excluded(7)))
+        );
+    }
+
+    #[test]
+    fn diff_of_details() {
+        let a = test_detail();
+        assert!(diff_details(&a, &a).is_empty());
+
+        let mut b = test_detail();
+        b.entity_count = BigDecimal::from_str("0.0").unwrap();
+        assert!(diff_details(&a, &b).is_empty());
+
+        b.health = HealthType::Failed;
+        b.synced = false;
+        b.latest_ethereum_block_number = Some(BigDecimal::from(7));
+        b.reorg_count = 2;
+        let diff = diff_details(&a, &b);
+        assert_eq!(
+            Some((SubgraphHealth::Healthy, SubgraphHealth::Failed)),
+            diff.health
+        );
+        assert_eq!(Some((true, false)), diff.synced);
+        assert_eq!(
+            Some((None, Some(BigDecimal::from(7)))),
+            diff.latest_block_number
+        );
+        assert_eq!(Some((0, 2)), diff.reorg_count);
+        assert_eq!(None, diff.latest_block_hash);
+        assert_eq!(None, diff.entity_count);
+        assert_eq!(None, diff.current_reorg_depth);
+    }
+
+    #[test]
+    fn like_escaping() {
+        assert_eq!("team-a/", escape_like("team-a/"));
+        assert_eq!("100\\%\\_done", escape_like("100%_done"));
+        assert_eq!("a\\\\b", escape_like("a\\b"));
+    }
+
+    #[test]
+    fn statement_timeout_errors() {
+        fn db_error(msg: &str) -> StoreError {
+            StoreError::from(diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::__Unknown,
+                Box::new(msg.to_string()),
+            ))
+        }
+
+        assert!(is_statement_timeout(&db_error(
+            "canceling statement due to statement timeout"
+        )));
+        assert!(!is_statement_timeout(&db_error(
+            "canceling statement due to conflict with recovery"
+        )));
+        assert!(!is_statement_timeout(&StoreError::DatabaseUnavailable));
+    }
+
+    #[test]
+    fn network_lookup() {
+        let site = crate::primary::make_dummy_site(
+            DeploymentHash::new("QmTest").unwrap(),
+            crate::primary::Namespace::new("sgd1".to_string()).unwrap(),
+            "mainnet".to_string(),
+        );
+        let id = site.id;
+        let sites = vec![Arc::new(site)];
+
+        assert_eq!("mainnet", network_for_deployment(&sites, id).unwrap());
+        // A deployment with the same hash, but a different id, does not
+        // use this site
+        let other = DeploymentId::from(GraphDeploymentId::new(1));
+        assert!(matches!(
+            network_for_deployment(&sites, other),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+        assert!(matches!(
+            network_for_deployment(&[], id),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+    }
+
+    #[test]
+    fn prune_target() {
+        let mut detail = test_detail();
+        assert!(prune_safe_to(&detail, 5).is_err());
+
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(10));
+        assert!(prune_safe_to(&detail, 5).is_ok());
+        assert!(prune_safe_to(&detail, 9).is_ok());
+        assert!(prune_safe_to(&detail, 10).is_err());
+        assert!(prune_safe_to(&detail, 11).is_err());
+
+        detail.graft_block_number = Some(BigDecimal::from(4));
+        assert!(prune_safe_to(&detail, 3).is_err());
+        assert!(prune_safe_to(&detail, 4).is_ok());
+        assert!(prune_safe_to(&detail, 5).is_ok());
+    }
+
+    #[test]
+    fn detail_builder() {
+        let id = DeploymentId::from(GraphDeploymentId::new(1));
+        let detail = DeploymentDetailBuilder::new(id, "QmTest").build();
+        assert!(detail.synced);
+        assert!(!detail.failed);
+        assert_eq!(
+            Some(BlockPtr::from((H256::zero(), 0u64))),
+            detail.latest_block_ptr().unwrap()
+        );
+        assert_eq!(BigDecimal::from(0), detail.entity_count);
+
+        let latest = BlockPtr::from((H256::from_low_u64_be(7), 7u64));
+        let detail = DeploymentDetailBuilder::new(id, "QmTest")
+            .health(SubgraphHealth::Failed)
+            .synced(false)
+            .latest_block(Some(&latest))
+            .entity_count(42)
+            .build();
+        assert!(!detail.synced);
+        assert!(detail.failed);
+        assert_eq!(Some(latest), detail.latest_block_ptr().unwrap());
+        assert_eq!(BigDecimal::from(42u64), detail.entity_count);
+    }
+
+    #[test]
+    fn entity_count_drift_sign() {
+        let mut detail = test_detail();
+        detail.entity_count = BigDecimal::from(10);
+        assert_eq!(0, entity_count_drift(&detail, 10).unwrap());
+        assert_eq!(5, entity_count_drift(&detail, 15).unwrap());
+        assert_eq!(-10, entity_count_drift(&detail, 0).unwrap());
+
+        detail.entity_count = BigDecimal::from(0);
+        assert!(entity_count_drift(&detail, u64::MAX).is_err());
+
+        detail.entity_count = BigDecimal::from(-1);
+        assert!(entity_count_drift(&detail, 0).is_err());
+    }
+
+    #[test]
+    fn etag_tracks_changes() {
+        let detail = test_detail();
+        let etag = detail_etag(&detail);
+        assert!(!etag.is_empty());
+        assert_eq!(etag, detail_etag(&test_detail()));
+
+        let mut changed = test_detail();
+        changed.latest_ethereum_block_number = Some(BigDecimal::from(1));
+        assert_ne!(etag, detail_etag(&changed));
+
+        let mut changed = test_detail();
+        changed.health = HealthType::Unhealthy;
+        assert_ne!(etag, detail_etag(&changed));
+
+        let mut changed = test_detail();
+        changed.entity_count = BigDecimal::from(1);
+        assert_ne!(etag, detail_etag(&changed));
+    }
+
+    #[test]
+    fn spec_version_gate() {
+        fn manifest(spec_version: &str) -> StoredSubgraphManifest {
+            StoredSubgraphManifest {
+                id: 1,
+                spec_version: spec_version.to_string(),
+                description: None,
+                repository: None,
+                features: vec![],
+                schema: String::new(),
+                graph_node_version_id: None,
+                use_bytea_prefix: true,
+            }
+        }
+
+        let m = manifest("0.0.5");
+        assert!(m.spec_version_at_least(0, 0, 4).unwrap());
+        assert!(m.spec_version_at_least(0, 0, 5).unwrap());
+        assert!(!m.spec_version_at_least(0, 0, 6).unwrap());
+        assert!(!m.spec_version_at_least(0, 1, 0).unwrap());
+
+        let m = manifest("0.0.5-rc1");
+        assert!(m.spec_version_at_least(0, 0, 4).unwrap());
+        assert!(!m.spec_version_at_least(0, 0, 5).unwrap());
+
+        assert!(manifest("0.0").spec_version_at_least(0, 0, 1).is_err());
+        assert!(manifest("latest").spec_version_at_least(0, 0, 1).is_err());
+    }
+
+    #[test]
+    fn sync_status_table() {
+        use HealthType as H;
+        use SyncStatus as S;
+
+        // (failed, health, synced, has latest block, expected)
+        let cases = [
+            (false, H::Healthy, false, false, S::NeverStarted),
+            (false, H::Healthy, true, false, S::NeverStarted),
+            (false, H::Healthy, false, true, S::Syncing),
+            (false, H::Healthy, true, true, S::Synced),
+            (false, H::Unhealthy, false, false, S::Unhealthy),
+            (false, H::Unhealthy, true, true, S::Unhealthy),
+            (false, H::Failed, false, true, S::Failed),
+            (true, H::Healthy, true, true, S::Failed),
+            (true, H::Unhealthy, false, false, S::Failed),
+        ];
+        for (failed, health, synced, has_latest, expected) in cases {
+            let mut detail = test_detail();
+            detail.failed = failed;
+            detail.health = health;
+            detail.synced = synced;
+            detail.latest_ethereum_block_number = has_latest.then(|| BigDecimal::from(1));
+            assert_eq!(
+                expected,
+                detail.sync_status(),
+                "failed={} health={:?} synced={} has_latest={}",
+                failed,
+                health,
+                synced,
+                has_latest
+            );
+        }
+    }
+
+    #[test]
+    fn reorg_check() {
+        let hash = H256::from_low_u64_be(3);
+        let mut detail = test_detail();
+        assert!(!reorg_detected(&detail, hash).unwrap());
+
+        let (latest_hash, latest_number) = block_columns(Some(&BlockPtr::from((hash, 3u64))));
+        detail.latest_ethereum_block_hash = latest_hash;
+        detail.latest_ethereum_block_number = latest_number;
+        assert!(!reorg_detected(&detail, hash).unwrap());
+        assert!(reorg_detected(&detail, H256::from_low_u64_be(4)).unwrap());
+
+        detail.latest_ethereum_block_hash = Some(vec![1, 2, 3]);
+        assert!(reorg_detected(&detail, hash).is_err());
+    }
+
+    #[test]
+    fn strict_networks() {
+        let info = |network: &str| {
+            status::Info::new_minimal(
+                "QmTest".to_string(),
+                network.to_string(),
+                true,
+                SubgraphHealth::Healthy,
+            )
+        };
+
+        assert!(check_networks(&[]).is_ok());
+        assert!(check_networks(&[info("mainnet")]).is_ok());
+        assert!(matches!(
+            check_networks(&[info("mainnet"), info("")]),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+    }
+
+    #[test]
+    fn graft_completion() {
+        let mut detail = test_detail();
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(10));
+        assert_eq!(None, detail.graft_complete());
+
+        detail.graft_base = Some("QmBase".to_string());
+        assert_eq!(None, detail.graft_complete());
+
+        detail.graft_block_number = Some(BigDecimal::from(10));
+        assert_eq!(Some(true), detail.graft_complete());
+
+        detail.graft_block_number = Some(BigDecimal::from(11));
+        assert_eq!(Some(false), detail.graft_complete());
+
+        detail.latest_ethereum_block_number = None;
+        assert_eq!(None, detail.graft_complete());
+    }
+
+    #[test]
+    fn graph_node_version_json() {
+        let version = GraphNodeVersion {
+            id: 1,
+            git_commit_hash: "abc123".to_string(),
+            git_repository_dirty: false,
+            crate_version: "0.26.0".to_string(),
+            major: 0,
+            minor: 26,
+            patch: 0,
+        };
+        let json = graph::prelude::serde_json::to_value(&version).unwrap();
+        assert_eq!(
+            graph::prelude::serde_json::json!({
+                "id": 1,
+                "git_commit_hash": "abc123",
+                "git_repository_dirty": false,
+                "crate_version": "0.26.0",
+                "major": 0,
+                "minor": 26,
+                "patch": 0,
+            }),
+            json
+        );
+    }
+
+    #[test]
+    fn block_pointer_invariants() {
+        let id = DeploymentId::from(GraphDeploymentId::new(1));
+        let ptr = |number: u64| BlockPtr::from((H256::from_low_u64_be(number), number));
+        let builder = || DeploymentDetailBuilder::new(id, "QmTest").latest_block(Some(&ptr(10)));
+
+        let detail = builder()
+            .earliest_block(Some(&ptr(2)))
+            .last_healthy_block(Some(&ptr(5)))
+            .graft("QmBase", &ptr(3))
+            .build();
+        assert!(validate_block_pointers(&detail).is_ok());
+
+        let detail = builder().earliest_block(Some(&ptr(11))).build();
+        assert!(matches!(
+            validate_block_pointers(&detail),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+
+        let detail = builder().last_healthy_block(Some(&ptr(11))).build();
+        assert!(matches!(
+            validate_block_pointers(&detail),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+
+        let detail = builder().graft("QmBase", &ptr(11)).build();
+        assert!(matches!(
+            validate_block_pointers(&detail),
+            Err(StoreError::ConstraintViolation(_))
+        ));
+
+        // Null block numbers are not checked
+        let detail = DeploymentDetailBuilder::new(id, "QmTest")
+            .earliest_block(None)
+            .latest_block(None)
+            .last_healthy_block(None)
+            .build();
+        assert!(validate_block_pointers(&detail).is_ok());
+    }
+
+    #[test]
+    fn chains_per_network() {
+        let id = DeploymentId::from(GraphDeploymentId::new(1));
+        let detail = || DeploymentDetailBuilder::new(id, "QmTest").build();
+        let networks = vec![
+            "mainnet".to_string(),
+            "gnosis".to_string(),
+            "mainnet".to_string(),
+        ];
+
+        let info = info_from_details(detail(), None, vec![], &[], &networks).unwrap();
+        let chains: Vec<_> = info.chains.iter().map(|c| c.network.as_str()).collect();
+        assert_eq!(vec!["mainnet", "gnosis"], chains);
+        for chain in &info.chains {
+            assert_eq!(0, chain.latest_block.as_ref().unwrap().number());
+        }
+
+        // Without networks, a site for the deployment is required
+        assert!(info_from_details(detail(), None, vec![], &[], &[]).is_err());
+    }
+
+    #[test]
+    fn chain_from_site_by_id() {
+        // Two copies of the same deployment in different shards
+        let site = |id: i32, network: &str| {
+            let mut site = crate::primary::make_dummy_site(
+                DeploymentHash::new("QmTest").unwrap(),
+                crate::primary::Namespace::new(format!("sgd{}", id)).unwrap(),
+                network.to_string(),
+            );
+            site.id = DeploymentId::from(GraphDeploymentId::new(id));
+            Arc::new(site)
+        };
+        let sites = vec![site(1, "mainnet"), site(2, "gnosis")];
+
+        let detail = DeploymentDetailBuilder::new(sites[1].id, "QmTest").build();
+        let info = info_from_details(detail, None, vec![], &sites, &[]).unwrap();
+        let chains: Vec<_> = info.chains.iter().map(|c| c.network.as_str()).collect();
+        assert_eq!(vec!["gnosis"], chains);
+    }
+
+    #[test]
+    fn statement_timeout_at_least_1ms() {
+        assert_eq!(
+            "set local statement_timeout = 1",
+            set_statement_timeout(Duration::ZERO)
+        );
+        assert_eq!(
+            "set local statement_timeout = 1",
+            set_statement_timeout(Duration::from_micros(500))
+        );
+        assert_eq!(
+            "set local statement_timeout = 2000",
+            set_statement_timeout(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn blocks_behind_head() {
+        let head = BigDecimal::from(100);
+
+        let mut detail = test_detail();
+        assert_eq!(None, detail.blocks_behind(&head));
+
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(90));
+        assert_eq!(Some(BigDecimal::from(10)), detail.blocks_behind(&head));
+
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(105));
+        assert_eq!(Some(BigDecimal::from(-5)), detail.blocks_behind(&head));
+    }
+
+    #[test]
+    fn latest_matches_ptr() {
+        let ptr = BlockPtr::from((H256::repeat_byte(7), 12));
+
+        let mut detail = test_detail();
+        assert!(!detail.latest_matches(&ptr).unwrap());
+
+        detail.latest_ethereum_block_hash = Some(vec![7u8; 32]);
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(12));
+        assert!(detail.latest_matches(&ptr).unwrap());
+
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(13));
+        assert!(!detail.latest_matches(&ptr).unwrap());
+
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(u64::MAX));
+        assert!(!detail.latest_matches(&ptr).unwrap());
+
+        detail.latest_ethereum_block_hash = Some(vec![8u8; 32]);
+        detail.latest_ethereum_block_number = Some(BigDecimal::from(12));
+        assert!(!detail.latest_matches(&ptr).unwrap());
+
+        detail.latest_ethereum_block_hash = Some(vec![7u8; 20]);
+        assert!(detail.latest_matches(&ptr).is_err());
+    }
+
+    #[test]
+    fn sync_progress_ratio() {
+        fn progress(earliest: Option<i32>, latest: Option<i32>, head: i32) -> Option<f64> {
+            let mut detail = test_detail();
+            detail.earliest_ethereum_block_number = earliest.map(BigDecimal::from);
+            detail.latest_ethereum_block_number = latest.map(BigDecimal::from);
+            let head = BlockPtr::from((H256::zero(), head));
+            sync_progress(&detail, &head)
+        }
+
+        assert_eq!(Some(0.0), progress(Some(100), Some(100), 200));
+        assert_eq!(Some(0.5), progress(Some(100), Some(150), 200));
+        assert_eq!(Some(1.0), progress(Some(100), Some(200), 200));
+        // The chain head is stale
+        assert_eq!(Some(1.0), progress(Some(100), Some(250), 200));
+        assert_eq!(None, progress(None, Some(150), 200));
+        assert_eq!(None, progress(Some(100), None, 200));
+        assert_eq!(None, progress(Some(200), Some(200), 200));
+    }
+
+    #[test]
+    fn describe_version_change() {
+        use VersionChange::*;
+
+        fn check(before: &str, after: &str, exp: VersionChange) {
+            let change = version(before, "abc").describe_change(&version(after, "abc"));
+            assert_eq!(exp, change, "{} -> {}", before, after);
+        }
+
+        check("0.26.0", "0.26.0", Same);
+        check("0.26.0", "0.26.1", Upgrade);
+        check("0.26.1", "0.26.0", Downgrade);
+        check("0.26.0", "0.27.0", Upgrade);
+        check("0.9.0", "0.10.0", Upgrade);
+        check("1.0.0", "0.99.99", Downgrade);
+
+        // Pre-releases rank below the release
+        check("1.0.0-rc1", "1.0.0", Upgrade);
+        check("1.0.0", "1.0.0-rc1", Downgrade);
+        check("1.0.0-rc1", "1.0.0-rc1", Same);
+        check("1.0.0-rc1", "1.0.0-rc2", Upgrade);
+        // Numeric identifiers are compared numerically and rank below
+        // alphanumeric ones
+        check("1.0.0-rc.2", "1.0.0-rc.10", Upgrade);
+        check("1.0.0-1", "1.0.0-alpha", Upgrade);
+        // More identifiers rank higher if all others are equal
+        check("1.0.0-alpha", "1.0.0-alpha.1", Upgrade);
+        check("1.0.0-alpha.1", "1.0.0-beta", Upgrade);
+        // The build version is not part of the order
+        check("1.0.0+build1", "1.0.0+build2", Same);
+        check("1.0.0-rc1", "1.0.0+build2", Upgrade);
+        check("0.26.0-rc1", "0.25.0", Downgrade);
+
+        let a = version("0.26.0", "abc");
+        let b = version("0.26.0", "def");
+        assert_eq!(SameVersionDifferentCommit, a.describe_change(&b));
+        let c = version("0.26.1", "def");
+        assert_eq!(Upgrade, a.describe_change(&c));
+    }
 }
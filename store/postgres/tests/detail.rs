@@ -0,0 +1,718 @@
+//! Tests for the deployment detail queries. The tests write deployment
+//! metadata directly into the tables in the primary; all changes are made
+//! in a transaction that is rolled back at the end of each test
+use diesel::connection::SimpleConnection as _;
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Integer, Text};
+use diesel::Connection as _;
+use diesel::RunQueryDsl as _;
+use graph::components::store::DeploymentId as GraphDeploymentId;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::prelude::{web3::types::H256, BlockPtr, DeploymentHash, StoreError};
+use graph_store_postgres::command_support::catalog::Site;
+use graph_store_postgres::command_support::detail;
+use graph_store_postgres::layout_for_tests::{make_dummy_site, Namespace, FAKE_NETWORK_SHARED};
+use std::sync::Arc;
+use test_store::*;
+
+/// Run `test` inside a transaction that gets rolled back. The
+/// `subgraph_deployment` and `subgraph_error` tables are empty when `test`
+/// starts
+fn run_test<F>(test: F)
+where
+    F: FnOnce(&PgConnection) -> Result<(), StoreError>,
+{
+    run_test_with_conn(|conn| {
+        conn.test_transaction(|| -> Result<(), StoreError> {
+            conn.batch_execute(
+                "delete from subgraphs.subgraph_error;
+                 delete from subgraphs.subgraph_deployment;",
+            )?;
+            test(conn)
+        })
+    })
+}
+
+/// Insert a healthy deployment `deployment` with id `id` that has not
+/// processed any blocks yet
+fn insert_deployment(conn: &PgConnection, id: i32, deployment: &str) -> Result<(), StoreError> {
+    conn.batch_execute(&format!(
+        "insert into subgraphs.subgraph_deployment
+           (id, deployment, failed, synced, health, non_fatal_errors, entity_count)
+         values ({}, '{}', false, false, 'healthy', '{{}}', 0)",
+        id, deployment
+    ))?;
+    Ok(())
+}
+
+/// Insert a site for the deployment `deployment` with id `id` that
+/// indexes `network`
+fn insert_site(
+    conn: &PgConnection,
+    id: i32,
+    deployment: &str,
+    network: &str,
+) -> Result<(), StoreError> {
+    conn.batch_execute(&format!(
+        "insert into public.deployment_schemas
+           (id, subgraph, name, shard, version, network, active)
+         values ({}, '{}', 'sgd{}', 'primary', 'relational', '{}', true)",
+        id, deployment, id, network
+    ))?;
+    Ok(())
+}
+
+/// Make a site for the deployment `deployment` with id `id` that indexes
+/// `network` without storing it in the database
+fn dummy_site(id: i32, deployment: &str, network: &str) -> Arc<Site> {
+    let mut site = make_dummy_site(
+        DeploymentHash::new(deployment).unwrap(),
+        Namespace::new(format!("sgd{}", id)).unwrap(),
+        network.to_string(),
+    );
+    site.id = GraphDeploymentId::new(id).into();
+    Arc::new(site)
+}
+
+/// Change the deployment with id `id` with the SQL `assignments`, e.g.,
+/// `synced = true`
+fn update_deployment(conn: &PgConnection, id: i32, assignments: &str) -> Result<(), StoreError> {
+    conn.batch_execute(&format!(
+        "update subgraphs.subgraph_deployment set {} where id = {}",
+        assignments, id
+    ))?;
+    Ok(())
+}
+
+/// Insert an error with id `id` for the deployment `deployment` that was
+/// current during the blocks in `block_range`, e.g., `[1,5)`
+fn insert_error(
+    conn: &PgConnection,
+    id: &str,
+    deployment: &str,
+    block_range: &str,
+) -> Result<(), StoreError> {
+    conn.batch_execute(&format!(
+        "insert into subgraphs.subgraph_error
+           (id, subgraph_id, message, deterministic, block_range)
+         values ('{}', '{}', 'error {}', false, '{}')",
+        id, deployment, id, block_range
+    ))?;
+    Ok(())
+}
+
+#[test]
+fn grafted_from() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "base")?;
+        insert_deployment(conn, 2, "graft")?;
+        insert_deployment(conn, 3, "plain")?;
+        insert_deployment(conn, 4, "otherGraft")?;
+        update_deployment(conn, 2, "graft_base = 'base'")?;
+        update_deployment(conn, 4, "graft_base = 'plain'")?;
+
+        let base = DeploymentHash::new("base").unwrap();
+        let grafts: Vec<_> = detail::deployments_grafted_from(conn, &base)?
+            .into_iter()
+            .map(|detail| detail.deployment)
+            .collect();
+        assert_eq!(vec!["graft".to_string()], grafts);
+
+        let unused = DeploymentHash::new("graft").unwrap();
+        assert!(detail::deployments_grafted_from(conn, &unused)?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn never_indexed() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "fresh")?;
+        insert_deployment(conn, 2, "indexed")?;
+        insert_deployment(conn, 3, "hashOnly")?;
+        insert_deployment(conn, 4, "numberOnly")?;
+        update_deployment(
+            conn,
+            2,
+            "latest_ethereum_block_hash = '\\x01', latest_ethereum_block_number = 1",
+        )?;
+        update_deployment(conn, 3, "latest_ethereum_block_hash = '\\x01'")?;
+        update_deployment(conn, 4, "latest_ethereum_block_number = 1")?;
+
+        // Whether a deployment has indexed anything is only determined by
+        // the block number
+        let mut ids: Vec<_> = detail::deployments_never_indexed(conn)?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect();
+        ids.sort();
+        assert_eq!(vec!["1".to_string(), "3".to_string()], ids);
+        Ok(())
+    })
+}
+
+#[test]
+fn max_reorg_below() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "shallow")?;
+        insert_deployment(conn, 2, "threshold")?;
+        insert_deployment(conn, 3, "deep")?;
+        update_deployment(conn, 1, "max_reorg_depth = 4")?;
+        update_deployment(conn, 2, "max_reorg_depth = 5")?;
+        update_deployment(conn, 3, "max_reorg_depth = 6")?;
+
+        let ids: Vec<_> = detail::deployments_with_max_reorg_below(conn, 5)?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(vec!["1".to_string()], ids);
+        Ok(())
+    })
+}
+
+#[test]
+fn delete_errors_before_block() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "failed")?;
+        insert_error(conn, "old", "failed", "[1,5)")?;
+        insert_error(conn, "fatal", "failed", "[2,6)")?;
+        insert_error(conn, "recent", "failed", "[8,12)")?;
+        insert_error(conn, "current", "failed", "[9,)")?;
+        update_deployment(
+            conn,
+            1,
+            "failed = true, health = 'failed', fatal_error = 'fatal'",
+        )?;
+
+        let count = detail::delete_errors_before_block(conn, "failed", 10)?;
+        assert_eq!(1, count);
+
+        let mut messages: Vec<_> = detail::errors_since_vid(conn, "failed", 0)?
+            .into_iter()
+            .map(|error| error.error.message)
+            .collect();
+        messages.sort();
+        assert_eq!(
+            vec!["error current", "error fatal", "error recent"],
+            messages
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn orphaned_errors() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "present")?;
+        insert_error(conn, "kept", "present", "[1,)")?;
+        // The foreign key on `subgraph_id` makes orphans impossible to
+        // create normally; turn off its trigger for the insert
+        conn.batch_execute("set local session_replication_role = replica")?;
+        insert_error(conn, "orphan", "deleted", "[1,)")?;
+        conn.batch_execute("set local session_replication_role = origin")?;
+
+        let orphans = detail::orphaned_errors(conn)?;
+        assert_eq!(1, orphans.len());
+
+        assert_eq!(1, detail::delete_orphaned_errors(conn)?);
+        assert!(detail::orphaned_errors(conn)?.is_empty());
+
+        let messages: Vec<_> = detail::errors_since_vid(conn, "present", 0)?
+            .into_iter()
+            .map(|error| error.error.message)
+            .collect();
+        assert_eq!(vec!["error kept"], messages);
+        Ok(())
+    })
+}
+
+#[test]
+fn changed_since() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "first")?;
+        insert_deployment(conn, 2, "second")?;
+
+        let changed = detail::deployment_details_changed_since(conn, 0)?;
+        let deployments: Vec<_> = changed.iter().map(|d| d.deployment.as_str()).collect();
+        assert_eq!(vec!["first", "second"], deployments);
+        let watermark = changed.last().unwrap().change_seq;
+
+        assert!(detail::deployment_details_changed_since(conn, watermark)?.is_empty());
+
+        update_deployment(conn, 1, "synced = true")?;
+        let changed = detail::deployment_details_changed_since(conn, watermark)?;
+        assert_eq!(1, changed.len());
+        assert!(changed[0].change_seq > watermark);
+        assert_eq!("first", changed[0].deployment);
+        assert!(changed[0].synced);
+        Ok(())
+    })
+}
+
+#[test]
+fn first_error() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "erring")?;
+        insert_deployment(conn, 2, "clean")?;
+        insert_error(conn, "early", "erring", "[5,)")?;
+        // Recorded later, even though it is for an earlier block
+        insert_error(conn, "late", "erring", "[1,)")?;
+
+        let error = detail::first_error(conn, "erring")?.unwrap();
+        assert_eq!("error early", error.message);
+        assert!(detail::first_error(conn, "clean")?.is_none());
+        Ok(())
+    })
+}
+
+#[test]
+fn total_reorg_count() {
+    run_test(|conn| {
+        // Use ids that do not clash with the sites of other tests
+        insert_deployment(conn, 1001, "reorgOne")?;
+        insert_deployment(conn, 1002, "reorgTwo")?;
+        insert_site(conn, 1001, "reorgOne", NETWORK_NAME)?;
+        insert_site(conn, 1002, "reorgTwo", FAKE_NETWORK_SHARED)?;
+        update_deployment(conn, 1001, "reorg_count = 3")?;
+        update_deployment(conn, 1002, "reorg_count = 4")?;
+
+        assert_eq!(7, detail::total_reorg_count(conn, None)?);
+        assert_eq!(3, detail::total_reorg_count(conn, Some(NETWORK_NAME))?);
+        assert_eq!(0, detail::total_reorg_count(conn, Some("no_such_network"))?);
+        Ok(())
+    })
+}
+
+#[test]
+fn below_graft() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "base")?;
+        insert_deployment(conn, 2, "valid")?;
+        insert_deployment(conn, 3, "invalid")?;
+        update_deployment(
+            conn,
+            2,
+            "graft_base = 'base', graft_block_number = 10, latest_ethereum_block_number = 12",
+        )?;
+        update_deployment(
+            conn,
+            3,
+            "graft_base = 'base', graft_block_number = 10, latest_ethereum_block_number = 8",
+        )?;
+
+        let ids: Vec<_> = detail::deployments_below_graft(conn)?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(vec!["3".to_string()], ids);
+        Ok(())
+    })
+}
+
+/// Return the health of the deployment with id `id`
+fn health(conn: &PgConnection, id: i32) -> Result<String, StoreError> {
+    sql::<Text>(&format!(
+        "select health::text from subgraphs.subgraph_deployment where id = {}",
+        id
+    ))
+    .get_result::<String>(conn)
+    .map_err(StoreError::from)
+}
+
+#[test]
+fn mark_deployments_health() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "good")?;
+        insert_deployment(conn, 2, "other")?;
+        insert_deployment(conn, 3, "failed")?;
+        update_deployment(conn, 3, "failed = true, health = 'failed'")?;
+        let good = GraphDeploymentId::new(1).into();
+        let other = GraphDeploymentId::new(2).into();
+        let failed = GraphDeploymentId::new(3).into();
+
+        let count = detail::mark_deployments_health(
+            conn,
+            &[good, other],
+            SubgraphHealth::Unhealthy,
+            false,
+        )?;
+        assert_eq!(2, count);
+        assert_eq!("unhealthy", health(conn, 1)?);
+        assert_eq!("unhealthy", health(conn, 2)?);
+
+        // Moving a failed deployment to any other health is rejected, and
+        // leaves all deployments unchanged
+        for target in [SubgraphHealth::Healthy, SubgraphHealth::Unhealthy] {
+            let res = detail::mark_deployments_health(conn, &[good, failed], target, false);
+            assert!(matches!(res, Err(StoreError::ConstraintViolation(_))));
+            assert_eq!("unhealthy", health(conn, 1)?);
+            assert_eq!("failed", health(conn, 3)?);
+        }
+
+        // .. unless it is forced
+        let count =
+            detail::mark_deployments_health(conn, &[failed], SubgraphHealth::Unhealthy, true)?;
+        assert_eq!(1, count);
+        assert_eq!("unhealthy", health(conn, 3)?);
+        let detail =
+            detail::deployment_detail_by_hash(conn, &DeploymentHash::new("failed").unwrap())?
+                .unwrap();
+        assert!(!detail.failed);
+        Ok(())
+    })
+}
+
+#[test]
+fn lenient_statuses() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "good")?;
+        insert_deployment(conn, 2, "corrupt")?;
+        // A block hash must be 32 bytes long
+        update_deployment(
+            conn,
+            2,
+            "latest_ethereum_block_hash = '\\x01', latest_ethereum_block_number = 1",
+        )?;
+        let sites = vec![
+            dummy_site(1, "good", NETWORK_NAME),
+            dummy_site(2, "corrupt", NETWORK_NAME),
+        ];
+
+        let (infos, errors) = detail::deployment_statuses_lenient(conn, &sites)?;
+        assert_eq!(1, infos.len());
+        assert_eq!("good", infos[0].subgraph);
+        assert_eq!(1, errors.len());
+        assert_eq!("corrupt", errors[0].0);
+        assert!(matches!(errors[0].1, StoreError::ConstraintViolation(_)));
+        Ok(())
+    })
+}
+
+#[test]
+fn strict_statuses() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "blankNetwork")?;
+        let sites = vec![dummy_site(1, "blankNetwork", "")];
+
+        let infos =
+            detail::deployment_statuses(&*LOGGER, conn, &sites, detail::StatusOrder::Unordered)?;
+        assert_eq!(1, infos.len());
+        assert_eq!("", infos[0].chains[0].network);
+
+        let res = detail::deployment_statuses_strict(
+            &*LOGGER,
+            conn,
+            &sites,
+            detail::StatusOrder::Unordered,
+        );
+        assert!(matches!(res, Err(StoreError::ConstraintViolation(_))));
+        Ok(())
+    })
+}
+
+#[test]
+fn error_ranges() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "ranged")?;
+        insert_error(conn, "closed", "ranged", "[3,7)")?;
+        insert_error(conn, "open", "ranged", "[9,)")?;
+
+        let ranges: Vec<_> = detail::errors_since_vid(conn, "ranged", 0)?
+            .into_iter()
+            .map(|error| (error.range.start, error.range.end))
+            .collect();
+        assert_eq!(vec![(Some(3), Some(7)), (Some(9), None)], ranges);
+        Ok(())
+    })
+}
+
+#[test]
+fn active_networks() {
+    run_test(|conn| {
+        // Use ids that do not clash with the sites of other tests
+        insert_deployment(conn, 1001, "networkOne")?;
+        insert_deployment(conn, 1002, "networkTwo")?;
+        insert_site(conn, 1001, "networkOne", NETWORK_NAME)?;
+        insert_site(conn, 1002, "networkTwo", NETWORK_NAME)?;
+        // A site without a deployment
+        insert_site(conn, 1003, "networkShared", FAKE_NETWORK_SHARED)?;
+
+        let networks = detail::active_networks(conn)?;
+        assert_eq!(vec![NETWORK_NAME.to_string()], networks);
+        Ok(())
+    })
+}
+
+#[test]
+fn error_round_trip() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "erroring")?;
+
+        let hash = H256::from_low_u64_be(7);
+        let with_block = SubgraphError {
+            subgraph_id: DeploymentHash::new("erroring").unwrap(),
+            message: "at block".to_string(),
+            block_ptr: Some(BlockPtr::from((hash, 7u64))),
+            handler: Some("handleEvent".to_string()),
+            deterministic: true,
+            block_hash_only: None,
+        };
+        let without_block = SubgraphError {
+            message: "no block".to_string(),
+            block_ptr: None,
+            handler: None,
+            deterministic: false,
+            ..with_block.clone()
+        };
+
+        for error in [&with_block, &without_block] {
+            let row = detail::NewErrorDetail::from(error);
+            row.insert(conn)?;
+            // Writing the same error again does nothing
+            row.insert(conn)?;
+        }
+        // Errors in existing databases can have the `UNVERSIONED_RANGE`
+        // and a block hash
+        conn.batch_execute(&format!(
+            "insert into subgraphs.subgraph_error
+               (id, subgraph_id, message, block_hash, deterministic, block_range)
+             values ('legacy', 'erroring', 'legacy', '\\x{}07', false, '[-1,)')",
+            "00".repeat(31)
+        ))?;
+
+        let errors: Vec<_> = detail::errors_since_vid(conn, "erroring", 0)?
+            .into_iter()
+            .map(|error| error.error)
+            .collect();
+        assert_eq!(3, errors.len());
+
+        let read = &errors[0];
+        assert_eq!("at block", read.message);
+        assert_eq!(with_block.block_ptr, read.block_ptr);
+        assert_eq!(Some(hash), read.block_hash_only);
+        assert_eq!(with_block.handler, read.handler);
+        assert!(read.deterministic);
+
+        let read = &errors[1];
+        assert_eq!("no block", read.message);
+        assert_eq!(None, read.block_ptr);
+        assert_eq!(None, read.block_hash_only);
+        assert_eq!(None, read.handler);
+        assert!(!read.deterministic);
+
+        let read = &errors[2];
+        assert_eq!("legacy", read.message);
+        assert_eq!(None, read.block_ptr);
+        assert_eq!(Some(hash), read.block_hash_only);
+        Ok(())
+    })
+}
+
+#[test]
+fn error_counts_by_handler() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "noisy")?;
+        insert_error(conn, "e1", "noisy", "[1,)")?;
+        insert_error(conn, "e2", "noisy", "[2,)")?;
+        insert_error(conn, "e3", "noisy", "[3,)")?;
+        insert_error(conn, "e4", "noisy", "[4,)")?;
+        insert_error(conn, "e5", "noisy", "[5,)")?;
+        insert_error(conn, "e6", "noisy", "[6,)")?;
+        conn.batch_execute(
+            "update subgraphs.subgraph_error set handler = 'handleTransfer'
+              where id in ('e1', 'e2', 'e3');
+             update subgraphs.subgraph_error set handler = 'handleMint'
+              where id = 'e4'",
+        )?;
+
+        let counts = detail::error_counts_by_handler(conn, "noisy")?;
+        assert_eq!(
+            vec![
+                (Some("handleTransfer".to_string()), 3),
+                // Errors without a handler are grouped together
+                (None, 2),
+                (Some("handleMint".to_string()), 1)
+            ],
+            counts
+        );
+
+        assert!(detail::error_counts_by_handler(conn, "quiet")?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn fatal_error_history() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "flaky")?;
+        insert_error(conn, "late", "flaky", "[5,7)")?;
+        insert_error(conn, "early", "flaky", "[2,4)")?;
+        insert_error(conn, "nonFatal", "flaky", "[3,)")?;
+        conn.batch_execute(
+            "update subgraphs.subgraph_error set deterministic = true
+              where id in ('late', 'early')",
+        )?;
+
+        let messages: Vec<_> = detail::fatal_error_history(conn, "flaky")?
+            .into_iter()
+            .map(|error| error.message)
+            .collect();
+        assert_eq!(
+            vec!["error early".to_string(), "error late".to_string()],
+            messages
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn in_snapshot() {
+    // `in_snapshot` starts its own transaction and can therefore not be
+    // run inside the transaction that `run_test` sets up. The changes this
+    // test makes are committed, and it therefore uses its own table
+    run_test_with_conn(|conn| {
+        conn.batch_execute(
+            "drop table if exists public.snapshot_test;
+             create table public.snapshot_test(value int not null);
+             insert into public.snapshot_test values (1);",
+        )
+        .unwrap();
+        let value = || {
+            diesel::select(sql::<Integer>("(select value from public.snapshot_test)"))
+                .get_result::<i32>(conn)
+                .map_err(StoreError::from)
+        };
+
+        let pool = primary_pool();
+        let other = pool.get().unwrap();
+        let (level, first, second) = detail::in_snapshot(conn, || {
+            let level = diesel::select(sql::<Text>("current_setting('transaction_isolation')"))
+                .get_result::<String>(conn)?;
+            let first = value()?;
+            other.batch_execute("update public.snapshot_test set value = 2")?;
+            Ok((level, first, value()?))
+        })
+        .unwrap();
+        assert_eq!("repeatable read", level);
+        // The second read does not see the change made by the other
+        // connection
+        assert_eq!(1, first);
+        assert_eq!(1, second);
+        assert_eq!(2, value().unwrap());
+
+        // Inside a transaction, we can not get a snapshot
+        let res = conn.transaction(|| detail::in_snapshot(conn, value));
+        assert!(matches!(res, Err(StoreError::ConstraintViolation(_))));
+
+        conn.batch_execute("drop table public.snapshot_test")
+            .unwrap();
+    })
+}
+
+#[test]
+fn fleet_metrics() {
+    run_test(|conn| {
+        assert_eq!(
+            detail::FleetMetrics::default(),
+            detail::fleet_metrics(conn)?
+        );
+
+        insert_deployment(conn, 1, "healthySynced")?;
+        insert_deployment(conn, 2, "healthy")?;
+        insert_deployment(conn, 3, "failed")?;
+        update_deployment(conn, 1, "synced = true, entity_count = 10")?;
+        update_deployment(conn, 2, "entity_count = 5")?;
+        update_deployment(conn, 3, "failed = true, health = 'failed'")?;
+
+        let metrics = detail::fleet_metrics(conn)?;
+        assert_eq!(
+            detail::FleetMetrics {
+                deployments_total: 3,
+                deployments_healthy: 2,
+                deployments_unhealthy: 0,
+                deployments_failed: 1,
+                deployments_synced: 1,
+                entity_count_sum: 15.0,
+            },
+            metrics
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn error_at_block() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "broken")?;
+        insert_error(conn, "old", "broken", "[1,5)")?;
+        insert_error(conn, "new", "broken", "[3,)")?;
+
+        let message = |block| -> Result<Option<String>, StoreError> {
+            Ok(detail::error_at_block(conn, "broken", block)?.map(|error| error.message))
+        };
+        assert_eq!(None, message(0)?);
+        assert_eq!(Some("error old".to_string()), message(1)?);
+        // Both errors contain block 3, and the later one wins
+        assert_eq!(Some("error new".to_string()), message(3)?);
+        assert_eq!(Some("error new".to_string()), message(5)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn errors_page() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "paged")?;
+        for i in 0..5 {
+            insert_error(conn, &format!("e{}", i), "paged", "[1,)")?;
+        }
+
+        let page = |offset, limit| -> Result<(Vec<String>, i64), StoreError> {
+            let (errors, total) = detail::errors_page(conn, "paged", offset, limit)?;
+            let messages = errors.into_iter().map(|error| error.message).collect();
+            Ok((messages, total))
+        };
+        assert_eq!(
+            (vec!["error e0".to_string(), "error e1".to_string()], 5),
+            page(0, 2)?
+        );
+        assert_eq!((vec!["error e4".to_string()], 5), page(4, 2)?);
+        // Past the end, we still get the total
+        assert_eq!((vec![], 5), page(10, 2)?);
+        let (errors, total) = detail::errors_page(conn, "unknown", 0, 2)?;
+        assert!(errors.is_empty());
+        assert_eq!(0, total);
+        Ok(())
+    })
+}
+
+#[test]
+fn failed_summaries() {
+    run_test(|conn| {
+        insert_deployment(conn, 1, "fine")?;
+        insert_deployment(conn, 2, "broken")?;
+        insert_deployment(conn, 3, "brokenNoError")?;
+        insert_error(conn, "fatal", "broken", "[1,)")?;
+        update_deployment(
+            conn,
+            2,
+            "failed = true, health = 'failed', fatal_error = 'fatal'",
+        )?;
+        update_deployment(conn, 3, "failed = true, health = 'failed'")?;
+
+        let summaries: Vec<_> = detail::failed_summaries(conn)?.into_iter().collect();
+        assert_eq!(
+            vec![
+                (
+                    "broken".to_string(),
+                    SubgraphHealth::Failed,
+                    Some("error fatal".to_string())
+                ),
+                ("brokenNoError".to_string(), SubgraphHealth::Failed, None)
+            ],
+            summaries
+        );
+        Ok(())
+    })
+}
@@ -402,6 +402,7 @@ fn status() {
             block_ptr: Some(GENESIS_PTR.clone()),
             handler: None,
             deterministic: true,
+            block_hash_only: None,
         };
 
         store
@@ -488,6 +489,7 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            block_hash_only: None,
         };
 
         assert!(count() == 0);
@@ -503,6 +505,7 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            block_hash_only: None,
         };
 
         // Inserting the same error is allowed but ignored.
@@ -517,6 +520,7 @@ fn subgraph_error() {
             block_ptr: None,
             handler: None,
             deterministic: false,
+            block_hash_only: None,
         };
 
         transact_errors(&store, &deployment, BLOCKS[3].clone(), vec![error2])
@@ -549,6 +553,7 @@ fn fatal_vs_non_fatal() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            block_hash_only: None,
         };
 
         store
@@ -628,6 +633,7 @@ fn fail_unfail_deterministic_error() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true,
+            block_hash_only: None,
         };
 
         let writable = store
@@ -740,6 +746,7 @@ fn fail_unfail_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: false, // wrong determinism
+            block_hash_only: None,
         };
 
         // Fail the subraph with a NON-deterministic error.
@@ -772,6 +779,7 @@ fn fail_unfail_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[2].clone()), // wrong block
             handler: None,
             deterministic: true, // right determinism
+            block_hash_only: None,
         };
 
         // Fail the subgraph with an advanced block.
@@ -837,6 +845,7 @@ fn fail_unfail_non_deterministic_error() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: false,
+            block_hash_only: None,
         };
 
         let writable = store
@@ -962,6 +971,7 @@ fn fail_unfail_non_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[1].clone()),
             handler: None,
             deterministic: true, // wrong determinism
+            block_hash_only: None,
         };
 
         // Fail the subgraph with a DETERMININISTIC error.
@@ -991,6 +1001,7 @@ fn fail_unfail_non_deterministic_error_noop() {
             block_ptr: Some(BLOCKS[2].clone()), // wrong block
             handler: None,
             deterministic: false, // right determinism
+            block_hash_only: None,
         };
 
         // Fail the subgraph with a non-deterministic error, but with an advanced block.
@@ -561,6 +561,12 @@ pub fn primary_connection() -> graph_store_postgres::layout_for_tests::Connectio
     graph_store_postgres::layout_for_tests::Connection::new(conn)
 }
 
+/// The connection pool for the primary. Tests that need a connection in
+/// addition to the one from `run_test_with_conn` can get it from here
+pub fn primary_pool() -> ConnectionPool {
+    PRIMARY_POOL.clone()
+}
+
 pub fn primary_mirror() -> graph_store_postgres::layout_for_tests::Mirror {
     let pool = PRIMARY_POOL.clone();
     let map = HashMap::from_iter(Some((PRIMARY_SHARD.clone(), pool)));